@@ -1,3 +1,4 @@
+#[allow(unused_imports)]
 use std::{
     io::{BufWriter, Cursor},
     path::PathBuf,
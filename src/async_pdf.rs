@@ -0,0 +1,71 @@
+// PDF output to a tokio `AsyncWrite`. Pages are serialised by `PDF` into a
+// buffer, which is written out after each page, so at most one page is
+// held in memory.
+
+use std::path::Path;
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{Error, PageConfig, PageInfo, PDF};
+
+/// A PDF file written to an `AsyncWrite`, requires the `tokio` feature.
+///
+/// Only the output is asynchronous, images are still decoded and encoded
+/// on the calling task.
+pub struct AsyncPDF<W: AsyncWrite + Unpin> {
+    pdf: PDF<Vec<u8>>,
+    writer: W,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncPDF<W> {
+    pub async fn create(
+        writer: W,
+        default_page_config: PageConfig,
+        total_pages: usize,
+    ) -> std::io::Result<Self> {
+        let mut pdf = AsyncPDF {
+            pdf: PDF::create(Vec::new(), default_page_config, total_pages)?,
+            writer,
+        };
+        pdf.write_output().await?;
+        Ok(pdf)
+    }
+
+    pub async fn add_page_from_image(
+        &mut self,
+        image: image::DynamicImage,
+        outline: Option<String>,
+        page_config: Option<PageConfig>,
+    ) -> Result<PageInfo, Error> {
+        let info = self.pdf.add_page_from_image(image, outline, page_config)?;
+        self.write_output().await?;
+        Ok(info)
+    }
+
+    pub async fn add_page_from_path<P: AsRef<Path>>(
+        &mut self,
+        image_path: P,
+        outline: Option<String>,
+        page_config: Option<PageConfig>,
+    ) -> Result<PageInfo, Error> {
+        let format = image::ImageFormat::from_path(&image_path).ok();
+        let data = tokio::fs::read(image_path).await?;
+        let info = self
+            .pdf
+            .add_page_from_data(data, format, outline, page_config.as_ref())?;
+        self.write_output().await?;
+        Ok(info)
+    }
+
+    /// Write what the PDF has serialised so far
+    async fn write_output(&mut self) -> std::io::Result<()> {
+        let output = self.pdf.take_output()?;
+        self.writer.write_all(&output).await
+    }
+
+    pub async fn finish(mut self) -> std::io::Result<()> {
+        let output = self.pdf.finish_into_inner()?;
+        self.writer.write_all(&output).await?;
+        self.writer.flush().await
+    }
+}
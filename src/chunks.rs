@@ -0,0 +1,92 @@
+// PDF output pulled chunk by chunk. Each page is only encoded when the
+// chunk before it has been taken, so a slow reader holds back the encoding.
+
+use image::DynamicImage;
+
+use crate::{Error, PageConfig, PDF};
+
+/// The bytes of a PDF as an iterator of chunks: the header, one chunk per
+/// page, and the page tree, outlines and xref table at the end.
+/// Images are taken from `images` with their outline as the chunks are
+/// read. More images than `total_pages` end the chunks with
+/// `Error::PageOverflow`. See `into_stream` for an async `Stream`.
+pub struct Chunks<I: Iterator<Item = (DynamicImage, Option<String>)>> {
+    images: I,
+    // None once finished, or after an error
+    pdf: Option<PDF<Vec<u8>>>,
+}
+
+impl<I: Iterator<Item = (DynamicImage, Option<String>)>> Chunks<I> {
+    pub fn new<T: IntoIterator<IntoIter = I>>(
+        images: T,
+        default_page_config: PageConfig,
+        total_pages: usize,
+    ) -> std::io::Result<Self> {
+        let pdf = PDF::create(Vec::new(), default_page_config, total_pages)?;
+        Ok(Chunks {
+            images: images.into_iter(),
+            pdf: Some(pdf),
+        })
+    }
+
+    fn next_chunk(&mut self, pdf: &mut PDF<Vec<u8>>) -> Result<Option<Vec<u8>>, Error> {
+        let mut chunk = pdf.take_output()?;
+        // Skipped blank pages write nothing
+        while chunk.is_empty() {
+            match self.images.next() {
+                Some((image, outline)) => {
+                    pdf.add_page_from_image(image, outline, None)?;
+                    chunk = pdf.take_output()?;
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(chunk))
+    }
+}
+
+impl<I: Iterator<Item = (DynamicImage, Option<String>)>> Iterator for Chunks<I> {
+    type Item = Result<Vec<u8>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut pdf = self.pdf.take()?;
+        match self.next_chunk(&mut pdf) {
+            Ok(Some(chunk)) => {
+                self.pdf = Some(pdf);
+                Some(Ok(chunk))
+            }
+            Ok(None) => Some(pdf.finish_into_inner().map_err(Error::from)),
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+/// `Chunks` as a `futures_core::Stream`, requires the `stream` feature.
+///
+/// Pages are encoded inside `poll_next`, which blocks the executor thread
+/// for as long as a page takes to encode. On a shared runtime, iterate
+/// `Chunks` in `spawn_blocking` and send the chunks through a channel
+/// instead.
+#[cfg(feature = "stream")]
+pub struct ChunkStream<I: Iterator<Item = (DynamicImage, Option<String>)>>(Chunks<I>);
+
+#[cfg(feature = "stream")]
+impl<I: Iterator<Item = (DynamicImage, Option<String>)>> Chunks<I> {
+    pub fn into_stream(self) -> ChunkStream<I> {
+        ChunkStream(self)
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<I: Iterator<Item = (DynamicImage, Option<String>)> + Unpin> futures_core::Stream
+    for ChunkStream<I>
+{
+    type Item = Result<Vec<u8>, Error>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        std::task::Poll::Ready(self.get_mut().0.next())
+    }
+}
@@ -1,7 +1,7 @@
 // All units are px
 
 /// Page size, default is A4
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub enum PageSize {
     A4,
     Custom(u32, u32),
@@ -30,20 +30,79 @@ impl PageSize {
 }
 
 /// Page margin, default is None
+#[derive(Clone)]
 pub enum Margin {
     None,
     Vertical(u32),
     Horizontal(u32),
     Custom(u32, u32),
+    /// Distinct margin on each side
+    Sides {
+        top: u32,
+        right: u32,
+        bottom: u32,
+        left: u32,
+    },
+    /// Book margins, the inner (gutter) side is on the left of odd pages
+    /// and on the right of even pages
+    Mirrored {
+        top: u32,
+        bottom: u32,
+        inner: u32,
+        outer: u32,
+    },
 }
 
 impl Margin {
+    /// Total horizontal and vertical margin, saturating at `u32::MAX` so
+    /// oversized margins are rejected as geometry errors
     pub fn dimensions(&self) -> (u32, u32) {
         match self {
             Margin::None => (0, 0),
             Margin::Vertical(margin) => (0, *margin),
             Margin::Horizontal(margin) => (*margin, 0),
             Margin::Custom(width, height) => (*width, *height),
+            Margin::Sides {
+                top,
+                right,
+                bottom,
+                left,
+            } => (left.saturating_add(*right), top.saturating_add(*bottom)),
+            Margin::Mirrored {
+                top,
+                bottom,
+                inner,
+                outer,
+            } => (inner.saturating_add(*outer), top.saturating_add(*bottom)),
+        }
+    }
+    /// Margin of each side as (top, right, bottom, left) for the given page.
+    /// Pages are counted from 0, so page 0 is the first (odd) page.
+    pub fn edges(&self, page: usize) -> (u32, u32, u32, u32) {
+        match self {
+            Margin::Sides {
+                top,
+                right,
+                bottom,
+                left,
+            } => (*top, *right, *bottom, *left),
+            Margin::Mirrored {
+                top,
+                bottom,
+                inner,
+                outer,
+            } => {
+                if page.is_multiple_of(2) {
+                    (*top, *outer, *bottom, *inner)
+                } else {
+                    (*top, *inner, *bottom, *outer)
+                }
+            }
+            // Split evenly between both sides
+            _ => {
+                let (x, y) = self.dimensions();
+                (y - y / 2, x - x / 2, y / 2, x / 2)
+            }
         }
     }
     pub fn new(x: u32, y: u32) -> Self {
@@ -55,9 +114,26 @@ impl Margin {
     pub fn horizontal(margin: u32) -> Self {
         Margin::Horizontal(margin)
     }
+    pub fn sides(top: u32, right: u32, bottom: u32, left: u32) -> Self {
+        Margin::Sides {
+            top,
+            right,
+            bottom,
+            left,
+        }
+    }
+    pub fn mirrored(top: u32, bottom: u32, inner: u32, outer: u32) -> Self {
+        Margin::Mirrored {
+            top,
+            bottom,
+            inner,
+            outer,
+        }
+    }
 }
 
 /// Alignment of the image
+#[derive(Clone)]
 pub enum Alignment {
     Center,
     Start,
@@ -65,13 +141,141 @@ pub enum Alignment {
     Custom(u32),
 }
 
+/// Clockwise rotation of the page when displayed, default is None
+#[derive(Clone, PartialEq)]
+pub enum Rotation {
+    None,
+    Clockwise90,
+    Clockwise180,
+    Clockwise270,
+}
+
+impl Rotation {
+    pub fn degrees(&self) -> u32 {
+        match self {
+            Rotation::None => 0,
+            Rotation::Clockwise90 => 90,
+            Rotation::Clockwise180 => 180,
+            Rotation::Clockwise270 => 270,
+        }
+    }
+}
+
+/// Cropping of scan borders, default is None
+#[derive(Clone)]
+pub enum Crop {
+    None,
+    /// Cut dark scanner-bed borders and white margins down to the content.
+    /// Pixels whose brightness is within the tolerance of black or white
+    /// count as border.
+    Auto(u8),
+}
+
+/// Cleanup filter for scans
+#[derive(Clone)]
+pub enum Filter {
+    /// Stretch the levels so the darkest and lightest 0.5% of pixels
+    /// become black and white
+    Normalize,
+    /// Turn every pixel at least this bright pure white
+    Whiten(u8),
+    /// Remove isolated specks with a 3x3 median
+    Despeckle,
+    /// Black and white with a global threshold chosen by Otsu's method
+    Otsu,
+    /// Black and white with a local Sauvola threshold, given the window
+    /// size in px and the sensitivity `k` (usually 0.2 - 0.5)
+    Sauvola(u32, f32),
+}
+
+/// Handling of blank pages, default is Keep.
+/// A page is blank when its ink coverage, the fraction of dark pixels,
+/// is below the given threshold (e.g. 0.002).
+#[derive(Clone)]
+pub enum BlankPage {
+    Keep,
+    /// Detect blank pages and report them in `PageInfo`, but keep them
+    Report(f32),
+    /// Detect blank pages and leave them out of the PDF
+    Skip(f32),
+}
+
+/// How page images are encoded, default is Jpeg
+#[derive(Clone, Copy, PartialEq)]
+pub enum Encoding {
+    /// A single JPEG image
+    Jpeg,
+    /// Mixed Raster Content, for colour scans of text. A full resolution
+    /// black and white mask keeps text sharp, while the background and the
+    /// colour of the text are low resolution JPEGs.
+    Mrc,
+    /// Flate compressed samples, without any loss. 16-bit images keep their
+    /// depth, for archival masters.
+    Lossless,
+    /// Flate compressed indices into a palette of at most this many colours
+    /// (up to 256), for comics and screenshots. Images with more colours
+    /// are quantised.
+    Indexed(u16),
+    /// JPEG 2000, better quality per byte on photographic scans. Requires
+    /// the `jpeg2000` feature, pages fail with an unsupported error without
+    /// it.
+    Jpeg2000,
+}
+
+/// Resolution of the colour channels of JPEG images, default is Chroma444
+#[derive(Clone, Copy, PartialEq)]
+pub enum Subsampling {
+    /// Full resolution colour
+    Chroma444,
+    /// Colour at half the horizontal resolution
+    Chroma422,
+    /// Colour at half the resolution in both directions, smallest files
+    Chroma420,
+}
+
+/// Automatic choice of the JPEG quality of each page, default is None.
+/// The chosen quality is reported in `PageInfo`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Target {
+    /// Use the configured quality
+    None,
+    /// Highest quality whose JPEG fits in this many bytes
+    Size(usize),
+    /// Lowest quality whose JPEG reaches this SSIM (0 to 1) against the page
+    Ssim(f32),
+}
+
+/// Handling of transparent images, default is SoftMask
+#[derive(Clone)]
+pub enum Alpha {
+    /// Keep the transparency as a soft mask. Images are flattened onto
+    /// white instead when deskewing, filters or MRC are used.
+    SoftMask,
+    /// Paint transparent areas with this RGB colour
+    Flatten(u8, u8, u8),
+}
+
 /// Config of a single page
+#[derive(Clone)]
 pub struct PageConfig {
     pub(crate) size: PageSize,
     pub(crate) margin: Margin,
     pub(crate) vertical_alignment: Alignment,
     pub(crate) horizontal_alignment: Alignment,
     pub(crate) quality: u8,
+    pub(crate) auto_rotate: bool,
+    pub(crate) rotation: Rotation,
+    pub(crate) deskew: bool,
+    pub(crate) crop: Crop,
+    pub(crate) filters: Vec<Filter>,
+    pub(crate) blank_page: BlankPage,
+    pub(crate) encoding: Encoding,
+    pub(crate) alpha: Alpha,
+    pub(crate) passthrough: bool,
+    pub(crate) subsampling: Subsampling,
+    pub(crate) progressive: bool,
+    pub(crate) optimize: bool,
+    pub(crate) target: Target,
 }
 
 impl Default for PageConfig {
@@ -83,6 +287,19 @@ impl Default for PageConfig {
             vertical_alignment: Alignment::Center,
             horizontal_alignment: Alignment::Center,
             quality: 60,
+            auto_rotate: true,
+            rotation: Rotation::None,
+            deskew: false,
+            crop: Crop::None,
+            filters: Vec::new(),
+            blank_page: BlankPage::Keep,
+            encoding: Encoding::Jpeg,
+            alpha: Alpha::SoftMask,
+            passthrough: false,
+            subsampling: Subsampling::Chroma444,
+            progressive: false,
+            optimize: false,
+            target: Target::None,
         }
     }
 }
@@ -112,4 +329,98 @@ impl PageConfig {
         self.quality = quality;
         self
     }
+
+    /// Rotate images read from a path according to their EXIF orientation.
+    /// Enabled by default, requires the `exif` feature.
+    pub fn auto_rotate(mut self, auto_rotate: bool) -> Self {
+        self.auto_rotate = auto_rotate;
+        self
+    }
+
+    /// Rotate the page with `/Rotate`, the image itself is left untouched
+    pub fn rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Detect and straighten slightly rotated scans, default is false.
+    /// The detected angle is reported in `PageInfo::skew`.
+    pub fn deskew(mut self, deskew: bool) -> Self {
+        self.deskew = deskew;
+        self
+    }
+
+    /// Crop borders before the image is placed on the page
+    pub fn crop(mut self, crop: Crop) -> Self {
+        self.crop = crop;
+        self
+    }
+
+    /// Add a cleanup filter, filters run in the order they are added
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Detect and optionally skip blank pages, such as duplex backsides.
+    /// Skipped pages and their outlines are not written and do not count
+    /// towards `total_pages`.
+    pub fn blank_page(mut self, blank_page: BlankPage) -> Self {
+        self.blank_page = blank_page;
+        self
+    }
+
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn alpha(mut self, alpha: Alpha) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    pub fn subsampling(mut self, subsampling: Subsampling) -> Self {
+        self.subsampling = subsampling;
+        self
+    }
+
+    /// Write progressive JPEGs, default is false. They are usually a bit
+    /// smaller and render coarse to fine.
+    pub fn progressive(mut self, progressive: bool) -> Self {
+        self.progressive = progressive;
+        self
+    }
+
+    /// Build Huffman tables for each JPEG instead of using the standard
+    /// ones, default is false. Smaller files, slower encoding.
+    pub fn optimize(mut self, optimize: bool) -> Self {
+        self.optimize = optimize;
+        self
+    }
+
+    /// Search the JPEG quality per page instead of using `quality`. Only
+    /// applies to the Jpeg encoding.
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Embed JPEG files, opaque non-interlaced PNG files and single strip
+    /// CCITT or JPEG TIFF pages without re-encoding them, default is false.
+    /// Only applies when no processing or EXIF rotation is needed.
+    pub fn passthrough(mut self, passthrough: bool) -> Self {
+        self.passthrough = passthrough;
+        self
+    }
+
+    /// Whether the page needs the decoded pixels of its image
+    pub(crate) fn needs_pixels(&self) -> bool {
+        !matches!(self.crop, Crop::None)
+            || self.deskew
+            || !self.filters.is_empty()
+            || !matches!(self.blank_page, BlankPage::Keep)
+            || self.encoding != Encoding::Jpeg
+            || self.target != Target::None
+    }
 }
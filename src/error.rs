@@ -3,6 +3,14 @@ pub enum Error {
     Io(std::io::Error),
     Image(image::ImageError),
     PageOverflow,
+    /// Margins leave no room for the image on the page
+    InvalidGeometry,
+    /// The file to append to is not a PDF written by this crate
+    InvalidPdf,
+    /// A split limit of zero pages or bytes
+    InvalidSplit,
+    /// A frame of a multi-frame file failed, with its index
+    Frame(usize, Box<Error>),
 }
 
 impl From<std::io::Error> for Error {
@@ -0,0 +1,182 @@
+// Inputs holding several images: multi-page TIFFs and animated GIFs and
+// WebPs. Frames are decoded one at a time, as pages are added.
+
+use std::io::Cursor;
+use std::rc::Rc;
+
+use image::codecs::{gif::GifDecoder, webp::WebPDecoder};
+use image::error::{DecodingError, UnsupportedError, UnsupportedErrorKind};
+use image::{AnimationDecoder, DynamicImage, ImageBuffer, ImageError, ImageFormat, RgbImage};
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::ColorType;
+
+use crate::pdf::Source;
+use crate::strips::read_strip;
+use crate::Error;
+
+pub type Frames = Box<dyn Iterator<Item = Result<Source, Error>>>;
+
+/// Whether a file holds more than one image, from its header
+pub fn has_frames(data: &[u8]) -> bool {
+    match image::guess_format(data) {
+        Ok(ImageFormat::Tiff) => Decoder::new(Cursor::new(data))
+            .map(|decoder| decoder.more_images())
+            .unwrap_or(false),
+        Ok(ImageFormat::Gif) => true,
+        // Animation flag of the extended header
+        Ok(ImageFormat::WebP) => {
+            data.get(12..16) == Some(b"VP8X") && data.get(20).is_some_and(|flags| flags & 0x02 != 0)
+        }
+        _ => false,
+    }
+}
+
+/// Frames of a multi-frame file, see `has_frames`. With passthrough,
+/// TIFF pages are embedded without decoding when possible.
+pub fn read_frames(data: Vec<u8>, passthrough: bool) -> Result<Frames, Error> {
+    match image::guess_format(&data)? {
+        ImageFormat::Tiff => {
+            let data: Rc<[u8]> = data.into();
+            let decoder = Decoder::new(Cursor::new(data.clone())).map_err(tiff_error)?;
+            Ok(Box::new(TiffPages {
+                decoder,
+                data,
+                passthrough,
+                done: false,
+            }))
+        }
+        ImageFormat::Gif => {
+            let frames = GifDecoder::new(Cursor::new(data))?.into_frames();
+            Ok(Box::new(frames.map(|frame| {
+                let image = DynamicImage::ImageRgba8(frame?.into_buffer());
+                Ok(Source::Decoded(image, None))
+            })))
+        }
+        _ => {
+            let frames = WebPDecoder::new(Cursor::new(data))?.into_frames();
+            Ok(Box::new(frames.map(|frame| {
+                let image = DynamicImage::ImageRgba8(frame?.into_buffer());
+                Ok(Source::Decoded(image, None))
+            })))
+        }
+    }
+}
+
+struct TiffPages {
+    decoder: Decoder<Cursor<Rc<[u8]>>>,
+    data: Rc<[u8]>,
+    passthrough: bool,
+    done: bool,
+}
+
+impl Iterator for TiffPages {
+    type Item = Result<Source, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let strip = if self.passthrough {
+            read_strip(&mut self.decoder, &self.data)
+        } else {
+            None
+        };
+        let page = match strip {
+            Some(image) => Ok(Source::Encoded(image, None)),
+            None => read_tiff_page(&mut self.decoder).map(|image| Source::Decoded(image, None)),
+        };
+        // Stop after a broken directory, the next one can't be found
+        self.done =
+            page.is_err() || !self.decoder.more_images() || self.decoder.next_image().is_err();
+
+        Some(page)
+    }
+}
+
+fn read_tiff_page(decoder: &mut Decoder<Cursor<Rc<[u8]>>>) -> Result<DynamicImage, Error> {
+    let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+    let color_type = decoder.colortype().map_err(tiff_error)?;
+
+    let image = match (decoder.read_image().map_err(tiff_error)?, color_type) {
+        // Bilevel scans and other packed gray samples
+        (DecodingResult::U8(data), ColorType::Gray(bits @ (1 | 2 | 4))) => {
+            let samples = expand_gray(&data, width, height, bits);
+            samples
+                .and_then(|samples| ImageBuffer::from_raw(width, height, samples))
+                .map(DynamicImage::ImageLuma8)
+        }
+        (DecodingResult::U8(data), ColorType::Gray(8)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma8)
+        }
+        (DecodingResult::U8(data), ColorType::GrayA(8)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA8)
+        }
+        (DecodingResult::U8(data), ColorType::RGB(8)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8)
+        }
+        (DecodingResult::U8(data), ColorType::RGBA(8)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8)
+        }
+        (DecodingResult::U8(data), ColorType::CMYK(8)) => {
+            let rgb = data
+                .chunks_exact(4)
+                .flat_map(|cmyk| {
+                    let white = 255 - cmyk[3] as u32;
+                    [0, 1, 2].map(|c| ((255 - cmyk[c] as u32) * white / 255) as u8)
+                })
+                .collect();
+            RgbImage::from_raw(width, height, rgb).map(DynamicImage::ImageRgb8)
+        }
+        (DecodingResult::U16(data), ColorType::Gray(16)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma16)
+        }
+        (DecodingResult::U16(data), ColorType::GrayA(16)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA16)
+        }
+        (DecodingResult::U16(data), ColorType::RGB(16)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb16)
+        }
+        (DecodingResult::U16(data), ColorType::RGBA(16)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba16)
+        }
+        _ => None,
+    };
+
+    image.ok_or_else(|| {
+        ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+            ImageFormat::Tiff.into(),
+            UnsupportedErrorKind::GenericFeature(format!("{:?}", color_type)),
+        ))
+        .into()
+    })
+}
+
+/// 8-bit samples from rows of packed `bits` bit samples, each row starting
+/// on a byte boundary
+fn expand_gray(data: &[u8], width: u32, height: u32, bits: u8) -> Option<Vec<u8>> {
+    let width = width as usize;
+    let stride = (width * bits as usize).div_ceil(8);
+    let max = (1u16 << bits) - 1;
+    let samples_per_byte = 8 / bits as usize;
+
+    let rows = data.chunks_exact(stride).take(height as usize);
+    if rows.len() < height as usize {
+        return None;
+    }
+    Some(
+        rows.flat_map(|row| {
+            (0..width).map(move |x| {
+                let byte = row[x / samples_per_byte];
+                let shift = 8 - bits as usize * (x % samples_per_byte + 1);
+                let value = (byte >> shift) as u16 & max;
+                (value * 255 / max) as u8
+            })
+        })
+        .collect(),
+    )
+}
+
+fn tiff_error(err: tiff::TiffError) -> Error {
+    ImageError::Decoding(DecodingError::new(ImageFormat::Tiff.into(), err)).into()
+}
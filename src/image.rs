@@ -1,73 +1,675 @@
 // Image related
 
+use std::collections::{hash_map::Entry, HashMap};
 use std::io::{Cursor, Write};
 
-use image::{DynamicImage, GenericImageView};
+use image::codecs::{jpeg::JpegDecoder, png::PngDecoder, tiff::TiffDecoder};
+use image::{
+    DynamicImage, GenericImageView, GrayImage, ImageBuffer, ImageDecoder, ImageFormat, Luma, Pixel,
+    Rgb, RgbImage,
+};
 
-use crate::{Alignment, Error, PageConfig, PageSize};
+use crate::jpeg::JpegInfo;
+use crate::{
+    Alignment, Alpha, Crop, Encoding, Error, Filter, PageConfig, PageInfo, PageSize, Subsampling,
+    Target,
+};
+
+/// Size of the page holding an image of the given dimensions
+pub fn page_dimensions(image: (u32, u32), page_config: &PageConfig) -> Result<(u32, u32), Error> {
+    match page_config.size {
+        // The page grows around the image
+        PageSize::Image => {
+            let (margin_x, margin_y) = page_config.margin.dimensions();
+            match (image.0.checked_add(margin_x), image.1.checked_add(margin_y)) {
+                (Some(width), Some(height)) => Ok((width, height)),
+                _ => Err(Error::InvalidGeometry),
+            }
+        }
+        _ => Ok(page_config.size.dimensions()),
+    }
+}
+
+/// Area left for the image once margins are taken from the page
+fn content_dimensions(page_config: &PageConfig) -> Result<(u32, u32), Error> {
+    let (page_width, page_height) = page_config.size.dimensions();
+    let (margin_x, margin_y) = page_config.margin.dimensions();
+
+    match (
+        page_width.checked_sub(margin_x),
+        page_height.checked_sub(margin_y),
+    ) {
+        (Some(width), Some(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(Error::InvalidGeometry),
+    }
+}
+
+/// Lowest and highest offset of the image along one axis,
+/// keeping `low_margin` and `high_margin` free on either side
+fn bounds(page: u32, image: u32, low_margin: u32, high_margin: u32) -> Result<(u32, u32), Error> {
+    let high = page
+        .checked_sub(high_margin)
+        .and_then(|space| space.checked_sub(image))
+        .filter(|high| *high >= low_margin)
+        .ok_or(Error::InvalidGeometry)?;
+
+    Ok((low_margin, high))
+}
+
+/// Size the image is shown at: shrunk to fit the page like `preprocess` does,
+/// for images that are embedded without being resized
+pub fn display_dimensions(
+    image: (u32, u32),
+    page_config: &PageConfig,
+) -> Result<(u32, u32), Error> {
+    let (width, height) = image;
+    if page_config.size == PageSize::Image {
+        return Ok(image);
+    }
+
+    let (content_width, content_height) = content_dimensions(page_config)?;
+    if width <= content_width && height <= content_height {
+        return Ok(image);
+    }
+
+    let ratio = f64::min(
+        content_width as f64 / width as f64,
+        content_height as f64 / height as f64,
+    );
+    Ok((
+        ((width as f64 * ratio).round() as u32).max(1),
+        ((height as f64 * ratio).round() as u32).max(1),
+    ))
+}
+
+pub fn preprocess(
+    mut image: image::DynamicImage,
+    page_config: &PageConfig,
+    info: &mut PageInfo,
+) -> Result<image::DynamicImage, Error> {
+    if image.color().has_alpha() {
+        // Only a plain JPEG page can carry a soft mask
+        let background = match page_config.alpha {
+            Alpha::Flatten(r, g, b) => Some([r, g, b]),
+            Alpha::SoftMask
+                if page_config.deskew
+                    || !page_config.filters.is_empty()
+                    || page_config.encoding == Encoding::Mrc =>
+            {
+                Some([255, 255, 255])
+            }
+            Alpha::SoftMask => None,
+        };
+        if let Some(background) = background {
+            image = flatten(&image, background);
+        }
+    }
+
+    if let Crop::Auto(tolerance) = page_config.crop {
+        if let Some((x, y, width, height)) = content_bounds(&image, tolerance) {
+            image = image.crop_imm(x, y, width, height);
+        }
+    }
+
+    if page_config.deskew {
+        let skew = detect_skew(&image);
+        if skew.abs() >= MIN_SKEW {
+            image = rotate(&image, skew);
+        }
+        info.skew = Some(skew);
+    }
 
-pub fn preprocess(mut image: image::DynamicImage, page_config: &PageConfig) -> image::DynamicImage {
     if page_config.size != PageSize::Image {
         // If image is larger than page, resize it
         let (width, height) = image.dimensions();
-        let (mut page_width, mut page_height) = page_config.size.dimensions();
-        let (margin_x, margin_y) = page_config.margin.dimensions();
-
-        page_width -= margin_x;
-        page_height -= margin_y;
+        let (content_width, content_height) = content_dimensions(page_config)?;
 
-        if width > page_width || height > page_height {
+        if width > content_width || height > content_height {
             image = image.resize(
-                page_width,
-                page_height,
+                content_width,
+                content_height,
                 image::imageops::FilterType::Lanczos3,
             );
         }
     }
 
+    // Filter last, so black and white output is not blurred by resizing
+    if !page_config.filters.is_empty() {
+        image = to_8bit(image);
+        for filter in &page_config.filters {
+            image = apply_filter(image, filter);
+        }
+    }
+
+    Ok(image)
+}
+
+/// Paint the transparent areas of an image with a background colour
+pub fn flatten(image: &DynamicImage, background: [u8; 3]) -> DynamicImage {
+    let rgba = image.to_rgba8();
+
+    let flat = RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let a = a as u32;
+        let blend = |value: u8, background: u8| {
+            ((value as u32 * a + background as u32 * (255 - a) + 127) / 255) as u8
+        };
+        Rgb([
+            blend(r, background[0]),
+            blend(g, background[1]),
+            blend(b, background[2]),
+        ])
+    });
+
+    if image.color().has_color() {
+        DynamicImage::ImageRgb8(flat)
+    } else {
+        DynamicImage::ImageLuma8(DynamicImage::ImageRgb8(flat).into_luma8())
+    }
+}
+
+/// 8-bit grayscale or RGB version of the image, the forms that filters and
+/// the JPEG encoder work with
+pub fn to_8bit(image: DynamicImage) -> DynamicImage {
+    match image {
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageRgb8(_) => image,
+        _ if image.color().has_color() => DynamicImage::ImageRgb8(image.into_rgb8()),
+        _ => DynamicImage::ImageLuma8(image.into_luma8()),
+    }
+}
+
+/// Brightness of an 8-bit grayscale or RGB pixel
+fn luma(channels: &[u8]) -> u8 {
+    match channels {
+        [r, g, b] => ((*r as u32 * 299 + *g as u32 * 587 + *b as u32 * 114) / 1000) as u8,
+        [value, ..] => *value,
+        [] => 0,
+    }
+}
+
+fn apply_filter(image: DynamicImage, filter: &Filter) -> DynamicImage {
+    match filter {
+        Filter::Normalize => normalize(image),
+        Filter::Whiten(threshold) => whiten(image, *threshold),
+        Filter::Despeckle => match image {
+            DynamicImage::ImageLuma8(buffer) => DynamicImage::ImageLuma8(median(&buffer)),
+            DynamicImage::ImageRgb8(buffer) => DynamicImage::ImageRgb8(median(&buffer)),
+            image => image,
+        },
+        Filter::Otsu => {
+            let gray = image.into_luma8();
+            let threshold = otsu_threshold(&gray);
+            DynamicImage::ImageLuma8(binarize(&gray, |_, _| threshold))
+        }
+        Filter::Sauvola(window, k) => {
+            DynamicImage::ImageLuma8(sauvola(&image.into_luma8(), *window, *k))
+        }
+    }
+}
+
+fn normalize(mut image: DynamicImage) -> DynamicImage {
+    let mut histogram = [0u64; 256];
+    for pixel in image.to_luma8().pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+
+    // Find the 0.5% and 99.5% levels
+    let clip = histogram.iter().sum::<u64>() / 200;
+    let mut seen = 0;
+    let low = histogram
+        .iter()
+        .position(|count| {
+            seen += count;
+            seen > clip
+        })
+        .unwrap_or(0) as u32;
+    seen = 0;
+    let high = 255
+        - histogram
+            .iter()
+            .rev()
+            .position(|count| {
+                seen += count;
+                seen > clip
+            })
+            .unwrap_or(0) as u32;
+
+    if high <= low {
+        return image;
+    }
+
+    let mut levels = [0u8; 256];
+    for (value, level) in levels.iter_mut().enumerate() {
+        *level = (((value as u32).clamp(low, high) - low) * 255 / (high - low)) as u8;
+    }
+
+    match &mut image {
+        DynamicImage::ImageLuma8(buffer) => {
+            buffer.iter_mut().for_each(|v| *v = levels[*v as usize])
+        }
+        DynamicImage::ImageRgb8(buffer) => buffer.iter_mut().for_each(|v| *v = levels[*v as usize]),
+        _ => {}
+    }
     image
 }
 
-pub fn get_operands(image: (u32, u32), page_config: &PageConfig) -> Vec<u8> {
+fn whiten(mut image: DynamicImage, threshold: u8) -> DynamicImage {
+    match &mut image {
+        DynamicImage::ImageLuma8(buffer) => whiten_pixels(buffer, threshold),
+        DynamicImage::ImageRgb8(buffer) => whiten_pixels(buffer, threshold),
+        _ => {}
+    }
+    image
+}
+
+fn whiten_pixels<P: Pixel<Subpixel = u8>>(buffer: &mut ImageBuffer<P, Vec<u8>>, threshold: u8) {
+    for pixel in buffer.pixels_mut() {
+        if luma(pixel.channels()) >= threshold {
+            pixel.channels_mut().iter_mut().for_each(|v| *v = 255);
+        }
+    }
+}
+
+/// 3x3 median of every channel
+fn median<P: Pixel<Subpixel = u8>>(buffer: &ImageBuffer<P, Vec<u8>>) -> ImageBuffer<P, Vec<u8>> {
+    let (width, height) = buffer.dimensions();
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let mut pixel = *buffer.get_pixel(x, y);
+        for c in 0..P::CHANNEL_COUNT as usize {
+            let mut values = [0u8; 9];
+            for (i, value) in values.iter_mut().enumerate() {
+                let sx = (x + i as u32 % 3).saturating_sub(1).min(width - 1);
+                let sy = (y + i as u32 / 3).saturating_sub(1).min(height - 1);
+                *value = buffer.get_pixel(sx, sy).channels()[c];
+            }
+            values.sort_unstable();
+            pixel.channels_mut()[c] = values[4];
+        }
+        pixel
+    })
+}
+
+/// Threshold that best separates the histogram into two classes
+fn otsu_threshold(gray: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in gray.pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+
+    let total = gray.pixels().len() as f64;
+    let sum: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(value, count)| value as f64 * *count as f64)
+        .sum();
+
+    let (mut best, mut best_variance) = (0, 0.0);
+    let (mut weight, mut weighted_sum) = (0.0, 0.0);
+    for (value, count) in histogram.iter().enumerate() {
+        weight += *count as f64;
+        weighted_sum += value as f64 * *count as f64;
+        if weight == 0.0 || weight == total {
+            continue;
+        }
+
+        let mean_low = weighted_sum / weight;
+        let mean_high = (sum - weighted_sum) / (total - weight);
+        let variance = weight * (total - weight) * (mean_low - mean_high).powi(2);
+        if variance > best_variance {
+            best = value;
+            best_variance = variance;
+        }
+    }
+
+    best as u8
+}
+
+/// Pixels above their threshold become white, the others black
+fn binarize(gray: &GrayImage, threshold: impl Fn(u32, u32) -> u8) -> GrayImage {
+    GrayImage::from_fn(gray.width(), gray.height(), |x, y| {
+        if gray.get_pixel(x, y).0[0] > threshold(x, y) {
+            Luma([255])
+        } else {
+            Luma([0])
+        }
+    })
+}
+
+/// Local threshold `mean * (1 + k * (deviation / 128 - 1))` over a window
+pub fn sauvola(gray: &GrayImage, window: u32, k: f32) -> GrayImage {
+    let (width, height) = gray.dimensions();
+    let stride = width as usize + 1;
+
+    // Integral images of values and squared values
+    let mut sums = vec![0u64; stride * (height as usize + 1)];
+    let mut squares = vec![0u64; stride * (height as usize + 1)];
+    for (x, y, pixel) in gray.enumerate_pixels() {
+        let value = pixel.0[0] as u64;
+        let (x, y) = (x as usize + 1, y as usize + 1);
+        sums[y * stride + x] = value + sums[(y - 1) * stride + x] + sums[y * stride + x - 1]
+            - sums[(y - 1) * stride + x - 1];
+        squares[y * stride + x] =
+            value * value + squares[(y - 1) * stride + x] + squares[y * stride + x - 1]
+                - squares[(y - 1) * stride + x - 1];
+    }
+
+    let half = window / 2;
+    binarize(gray, |x, y| {
+        let (x0, y0) = (
+            x.saturating_sub(half) as usize,
+            y.saturating_sub(half) as usize,
+        );
+        let (x1, y1) = (
+            (x + half + 1).min(width) as usize,
+            (y + half + 1).min(height) as usize,
+        );
+        let area = |table: &[u64]| {
+            (table[y1 * stride + x1] + table[y0 * stride + x0]
+                - table[y0 * stride + x1]
+                - table[y1 * stride + x0]) as f64
+        };
+
+        let count = ((x1 - x0) * (y1 - y0)) as f64;
+        let mean = area(&sums) / count;
+        let deviation = (area(&squares) / count - mean * mean).max(0.0).sqrt();
+
+        (mean * (1.0 + k as f64 * (deviation / 128.0 - 1.0))).clamp(0.0, 255.0) as u8
+    })
+}
+
+/// Bounds (x, y, width, height) of the content inside dark or white borders.
+/// Returns `None` if the whole image is border.
+pub fn content_bounds(image: &DynamicImage, tolerance: u8) -> Option<(u32, u32, u32, u32)> {
+    let gray = image.to_luma8();
+    let (width, height) = gray.dimensions();
+
+    let is_content = |x: u32, y: u32| {
+        let value = gray.get_pixel(x, y).0[0];
+        value > tolerance && value < 255 - tolerance
+    };
+    // Allow 1% of a border line to be noise
+    let is_border = |content: usize, length: u32| content * 100 <= length as usize;
+
+    let rows = |y: &u32| !is_border((0..width).filter(|x| is_content(*x, *y)).count(), width);
+    let top = (0..height).find(rows)?;
+    let bottom = (0..height).rev().find(rows)?;
+
+    let columns = |x: &u32| {
+        let content = (top..=bottom).filter(|y| is_content(*x, *y)).count();
+        !is_border(content, bottom - top + 1)
+    };
+    let left = (0..width).find(columns)?;
+    let right = (0..width).rev().find(columns)?;
+
+    Some((left, top, right - left + 1, bottom - top + 1))
+}
+
+/// Fraction of dark pixels, ignoring a band along the edges
+/// where scanner borders and shadows are common
+pub fn ink_coverage(image: &DynamicImage) -> f32 {
+    let gray = image.thumbnail(1000, 1000).to_luma8();
+    let (width, height) = gray.dimensions();
+    let (band_x, band_y) = (width / 20, height / 20);
+
+    let (mut ink, mut total) = (0u64, 0u64);
+    for (x, y, pixel) in gray.enumerate_pixels() {
+        if x < band_x || y < band_y || x >= width - band_x || y >= height - band_y {
+            continue;
+        }
+        total += 1;
+        if pixel.0[0] < 128 {
+            ink += 1;
+        }
+    }
+
+    if total == 0 {
+        0.0
+    } else {
+        ink as f32 / total as f32
+    }
+}
+
+/// Largest skew in degrees that deskewing looks for
+const MAX_SKEW: f32 = 10.0;
+/// Skews smaller than this are not worth resampling the image
+const MIN_SKEW: f32 = 0.05;
+
+/// Estimate how many degrees the content is rotated clockwise.
+///
+/// Uses the projection profile method: dark pixels are projected onto
+/// the vertical axis along lines of each candidate angle, and the angle
+/// giving the sharpest profile (text lines collapsing into peaks) wins.
+pub fn detect_skew(image: &DynamicImage) -> f32 {
+    // A small copy is enough to find the lines
+    let gray = image.thumbnail(1000, 1000).to_luma8();
+    let (width, height) = gray.dimensions();
+
+    let dark: Vec<(f32, f32)> = gray
+        .enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel.0[0] < 128)
+        .map(|(x, y, _)| (x as f32, y as f32))
+        .collect();
+
+    if dark.is_empty() {
+        return 0.0;
+    }
+
+    // Rows may move up or down by width * tan(MAX_SKEW)
+    let shift = (width as f32 * MAX_SKEW.to_radians().tan()).ceil();
+    let mut bins = vec![0u32; (height as f32 + 2.0 * shift) as usize + 1];
+
+    let mut score = |angle: f32| {
+        let slope = angle.to_radians().tan();
+        bins.iter_mut().for_each(|bin| *bin = 0);
+        for (x, y) in &dark {
+            bins[(y - x * slope + shift) as usize] += 1;
+        }
+        bins.iter().map(|bin| (*bin as u64).pow(2)).sum::<u64>()
+    };
+
+    // Coarse search, then refine around the best candidate.
+    // Only move away from a candidate if another one is strictly better,
+    // so pages without lines stay at 0.
+    let mut best = (0.0, score(0.0));
+    for (step, range) in [(0.5, MAX_SKEW), (0.05, 0.5)] {
+        let center = best.0;
+        let steps = (range / step).round() as i32;
+        for i in -steps..=steps {
+            let angle = center + i as f32 * step;
+            if angle.abs() > MAX_SKEW {
+                continue;
+            }
+            let value = score(angle);
+            if value > best.1 {
+                best = (angle, value);
+            }
+        }
+    }
+
+    best.0
+}
+
+/// Rotate the image counter-clockwise by `angle` degrees around its center,
+/// keeping its size and filling uncovered corners with white
+pub fn rotate(image: &DynamicImage, angle: f32) -> DynamicImage {
+    let source = image.to_rgb8();
+    let (width, height) = source.dimensions();
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let (center_x, center_y) = (width as f32 / 2.0, height as f32 / 2.0);
+
+    let rotated = RgbImage::from_fn(width, height, |x, y| {
+        let dx = x as f32 + 0.5 - center_x;
+        let dy = y as f32 + 0.5 - center_y;
+        // Sample the source pixel that ends up here, bilinearly
+        let sx = center_x + cos * dx - sin * dy - 0.5;
+        let sy = center_y + sin * dx + cos * dy - 0.5;
+
+        if sx < 0.0 || sy < 0.0 || sx > (width - 1) as f32 || sy > (height - 1) as f32 {
+            return Rgb([255, 255, 255]);
+        }
+
+        let (x0, y0) = (sx.floor() as u32, sy.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+        let (fx, fy) = (sx - x0 as f32, sy - y0 as f32);
+
+        let mut pixel = [0u8; 3];
+        for (c, value) in pixel.iter_mut().enumerate() {
+            let top = source.get_pixel(x0, y0).0[c] as f32 * (1.0 - fx)
+                + source.get_pixel(x1, y0).0[c] as f32 * fx;
+            let bottom = source.get_pixel(x0, y1).0[c] as f32 * (1.0 - fx)
+                + source.get_pixel(x1, y1).0[c] as f32 * fx;
+            *value = (top * (1.0 - fy) + bottom * fy).round() as u8;
+        }
+        Rgb(pixel)
+    });
+
+    DynamicImage::ImageRgb8(rotated)
+}
+
+/// Read the EXIF orientation (1-8) of an image file
+#[cfg(feature = "exif")]
+pub fn read_orientation(data: &[u8]) -> Option<u32> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()?;
+
+    exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+        .value
+        .get_uint(0)
+}
+
+#[cfg(not(feature = "exif"))]
+pub fn read_orientation(_data: &[u8]) -> Option<u32> {
+    None
+}
+
+/// Decode an image file, along with its embedded ICC profile. `hint` is
+/// the format to use when the data doesn't tell, as for TGA files
+pub fn decode(
+    data: &[u8],
+    hint: Option<ImageFormat>,
+) -> Result<(DynamicImage, Option<Vec<u8>>), Error> {
+    let format = image::guess_format(data).or_else(|err| hint.ok_or(err))?;
+
+    fn with_profile<'a, D: ImageDecoder<'a>>(
+        mut decoder: D,
+    ) -> Result<(DynamicImage, Option<Vec<u8>>), Error> {
+        let profile = decoder.icc_profile();
+        Ok((DynamicImage::from_decoder(decoder)?, profile))
+    }
+
+    match format {
+        ImageFormat::Jpeg => with_profile(JpegDecoder::new(Cursor::new(data))?),
+        ImageFormat::Png => with_profile(PngDecoder::new(Cursor::new(data))?),
+        ImageFormat::Tiff => with_profile(TiffDecoder::new(Cursor::new(data))?),
+        _ => Ok((image::load_from_memory_with_format(data, format)?, None)),
+    }
+}
+
+/// Read the embedded ICC profile of an image file, without decoding it
+pub fn read_icc_profile(data: &[u8]) -> Option<Vec<u8>> {
+    match image::guess_format(data).ok()? {
+        ImageFormat::Jpeg => JpegDecoder::new(Cursor::new(data)).ok()?.icc_profile(),
+        ImageFormat::Png => PngDecoder::new(Cursor::new(data)).ok()?.icc_profile(),
+        ImageFormat::Tiff => TiffDecoder::new(Cursor::new(data)).ok()?.icc_profile(),
+        _ => None,
+    }
+}
+
+/// Number of colour components an ICC profile describes
+pub fn icc_components(profile: &[u8]) -> Option<u8> {
+    // Colour space signature in the profile header
+    match profile.get(16..20)? {
+        b"GRAY" => Some(1),
+        b"RGB " => Some(3),
+        b"CMYK" => Some(4),
+        _ => None,
+    }
+}
+
+/// Turn the pixels upright according to an EXIF orientation
+pub fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        // 1 or unknown: already upright
+        _ => image,
+    }
+}
+
+pub fn get_operands(
+    image: (u32, u32),
+    page_config: &PageConfig,
+    page: usize,
+) -> Result<Vec<u8>, Error> {
     // Calculate 6 operands for the image
     let (image_width, image_height) = image;
 
-    let (page_width, page_height) = page_config.size.dimensions();
-    let (mut margin_x, mut margin_y) = page_config.margin.dimensions();
-    margin_x /= 2;
-    margin_y /= 2;
+    let (page_width, page_height) = page_dimensions(image, page_config)?;
+    let (top, right, bottom, left) = page_config.margin.edges(page);
 
-    // X position
+    // X position, centered within the margins
+    let (min_x, max_x) = bounds(page_width, image_width, left, right)?;
     let x = match page_config.horizontal_alignment {
-        Alignment::Start => margin_x,
-        Alignment::Center => ((page_width - image_width) / 2).max(margin_x),
-        Alignment::End => page_width - image_width - margin_x,
-        Alignment::Custom(x) => x.max(margin_x),
+        Alignment::Start => min_x,
+        Alignment::Center => min_x + (max_x - min_x) / 2,
+        Alignment::End => max_x,
+        Alignment::Custom(x) => x.clamp(min_x, max_x),
     };
 
     // Y position
+    let (min_y, max_y) = bounds(page_height, image_height, bottom, top)?;
     let y = match page_config.vertical_alignment {
-        Alignment::Start => page_height - image_height - margin_y,
-        Alignment::Center => ((page_height - image_height) / 2).max(margin_y),
-        Alignment::End => margin_y,
-        Alignment::Custom(y) => y.max(margin_y),
+        Alignment::Start => max_y,
+        Alignment::Center => min_y + (max_y - min_y) / 2,
+        Alignment::End => min_y,
+        Alignment::Custom(y) => y.clamp(min_y, max_y),
     };
 
-    format!(
+    Ok(format!(
         "{} {} {} {} {} {} cm\n",
         image_width, 0, 0, image_height, x, y
     )
-    .into_bytes()
+    .into_bytes())
 }
 
-pub fn encode_image(image: DynamicImage, quality: u8) -> Result<Vec<u8>, Error> {
-    let mut data = Vec::new();
+/// An encoded image, ready to be written as an image XObject
+pub struct EncodedImage {
+    pub width: u32,
+    pub height: u32,
+    /// e.g. `/DeviceRGB`
+    pub color_space: String,
+    pub bits_per_component: u8,
+    /// e.g. `[/FlateDecode /DCTDecode]`, `None` for raw samples
+    pub filter: Option<String>,
+    /// e.g. `[1 0 1 0 1 0 1 0]` to invert the samples
+    pub decode: Option<String>,
+    /// Parameters of the filter, e.g. `<< /K -1 /Columns 100 >>`
+    pub decode_parms: Option<String>,
+    pub data: Vec<u8>,
+}
 
-    image.write_to(
-        &mut Cursor::new(&mut data),
-        image::ImageOutputFormat::Jpeg(quality),
-    )?;
+impl EncodedImage {
+    /// Number of colour components, if the colour space is a device one
+    pub fn components(&self) -> Option<u8> {
+        match self.color_space.as_str() {
+            "/DeviceGray" => Some(1),
+            "/DeviceRGB" => Some(3),
+            "/DeviceCMYK" => Some(4),
+            _ => None,
+        }
+    }
+}
 
+/// Zlib-compress data if the `flate2` feature is enabled.
+/// Returns the data and the filter to decode it.
+pub fn deflate(data: Vec<u8>) -> Result<(Vec<u8>, Option<&'static str>), Error> {
     #[cfg(feature = "flate2")]
     {
         let mut encoder =
@@ -75,11 +677,370 @@ pub fn encode_image(image: DynamicImage, quality: u8) -> Result<Vec<u8>, Error>
 
         encoder.write_all(&data)?;
 
-        encoder.finish().map_err(Error::Io)
+        Ok((encoder.finish()?, Some("/FlateDecode")))
     }
 
     #[cfg(not(feature = "flate2"))]
     {
-        Ok(data)
+        Ok((data, None))
+    }
+}
+
+fn color_space(image: &DynamicImage) -> String {
+    if image.color().has_color() {
+        "/DeviceRGB".to_string()
+    } else {
+        "/DeviceGray".to_string()
     }
 }
+
+/// Encode an 8-bit image as JPEG
+fn jpeg_data(
+    image: &DynamicImage,
+    quality: u8,
+    page_config: &PageConfig,
+) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    let (width, height) = image.dimensions();
+    let color_type = if image.color().has_color() {
+        jpeg_encoder::ColorType::Rgb
+    } else {
+        jpeg_encoder::ColorType::Luma
+    };
+
+    let mut encoder = jpeg_encoder::Encoder::new(&mut data, quality);
+    encoder.set_sampling_factor(match page_config.subsampling {
+        Subsampling::Chroma444 => jpeg_encoder::SamplingFactor::R_4_4_4,
+        Subsampling::Chroma422 => jpeg_encoder::SamplingFactor::R_4_2_2,
+        Subsampling::Chroma420 => jpeg_encoder::SamplingFactor::R_4_2_0,
+    });
+    encoder.set_progressive(page_config.progressive);
+    encoder.set_optimized_huffman_tables(page_config.optimize);
+
+    let (jpeg_width, jpeg_height) = match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) => (width, height),
+        // Larger than the JPEG format allows
+        _ => {
+            return Err(
+                image::ImageError::Limits(image::error::LimitError::from_kind(
+                    image::error::LimitErrorKind::DimensionError,
+                ))
+                .into(),
+            )
+        }
+    };
+    encoder
+        .encode(image.as_bytes(), jpeg_width, jpeg_height, color_type)
+        .map_err(|err| {
+            image::ImageError::Encoding(image::error::EncodingError::new(
+                ImageFormat::Jpeg.into(),
+                err,
+            ))
+        })?;
+
+    Ok(data)
+}
+
+/// Binary search the JPEG quality meeting a target
+pub fn search_quality(
+    image: &DynamicImage,
+    target: Target,
+    page_config: &PageConfig,
+) -> Result<u8, Error> {
+    let image = to_8bit(image.clone());
+    let luma = image.to_luma8();
+
+    // Whether the quality is high enough for the target, or low enough
+    // for a byte budget
+    let meets = |quality: u8| -> Result<bool, Error> {
+        let data = jpeg_data(&image, quality, page_config)?;
+        Ok(match target {
+            Target::None => true,
+            // As written by `encode_image`, compressed again
+            Target::Size(budget) => deflate(data)?.0.len() <= budget,
+            Target::Ssim(target) => {
+                let decoded = image::load_from_memory_with_format(&data, ImageFormat::Jpeg)?;
+                ssim(&luma, &decoded.to_luma8()) >= target
+            }
+        })
+    };
+
+    let (mut low, mut high) = (1u8, 100u8);
+    match target {
+        // Highest passing quality, or the lowest one when none fits
+        Target::Size(_) => {
+            while low < high {
+                let mid = low + (high - low).div_ceil(2);
+                if meets(mid)? {
+                    low = mid;
+                } else {
+                    high = mid - 1;
+                }
+            }
+        }
+        // Lowest passing quality, or the highest one when none does
+        _ => {
+            while low < high {
+                let mid = low + (high - low) / 2;
+                if meets(mid)? {
+                    high = mid;
+                } else {
+                    low = mid + 1;
+                }
+            }
+        }
+    }
+
+    Ok(low)
+}
+
+/// Mean structural similarity of two grayscale images, over 8x8 blocks
+fn ssim(a: &GrayImage, b: &GrayImage) -> f32 {
+    const BLOCK: u32 = 8;
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let (width, height) = a.dimensions();
+    let (mut total, mut blocks) = (0.0, 0);
+    for by in (0..height.saturating_sub(BLOCK - 1)).step_by(BLOCK as usize) {
+        for bx in (0..width.saturating_sub(BLOCK - 1)).step_by(BLOCK as usize) {
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) =
+                (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in by..by + BLOCK {
+                for x in bx..bx + BLOCK {
+                    let va = a.get_pixel(x, y).0[0] as f64;
+                    let vb = b.get_pixel(x, y).0[0] as f64;
+                    sum_a += va;
+                    sum_b += vb;
+                    sum_aa += va * va;
+                    sum_bb += vb * vb;
+                    sum_ab += va * vb;
+                }
+            }
+
+            let n = (BLOCK * BLOCK) as f64;
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_b = sum_bb / n - mean_b * mean_b;
+            let covariance = sum_ab / n - mean_a * mean_b;
+
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            blocks += 1;
+        }
+    }
+
+    if blocks == 0 {
+        return 1.0;
+    }
+    (total / blocks as f64) as f32
+}
+
+pub fn encode_image(
+    image: DynamicImage,
+    quality: u8,
+    page_config: &PageConfig,
+) -> Result<EncodedImage, Error> {
+    let (width, height) = image.dimensions();
+    let color_space = color_space(&image);
+    let data = jpeg_data(&to_8bit(image), quality, page_config)?;
+
+    let (data, flate) = deflate(data)?;
+    let filter = match flate {
+        Some(flate) => format!("[{} /DCTDecode]", flate),
+        None => "/DCTDecode".to_string(),
+    };
+
+    Ok(EncodedImage {
+        width,
+        height,
+        color_space,
+        bits_per_component: 8,
+        filter: Some(filter),
+        decode: None,
+        decode_parms: None,
+        data,
+    })
+}
+
+/// Encode an image as indices into a palette of at most `colors` colours.
+/// Images with few enough colours keep them exactly, others are quantised.
+pub fn encode_indexed(image: &DynamicImage, colors: u16) -> Result<EncodedImage, Error> {
+    let (width, height) = image.dimensions();
+    let rgb = image.to_rgb8();
+    let colors = colors.clamp(2, 256) as usize;
+
+    // Exact palette, as long as the image has few enough colours
+    let mut palette: Vec<[u8; 3]> = Vec::new();
+    let mut lookup = HashMap::new();
+    for pixel in rgb.pixels() {
+        if let Entry::Vacant(entry) = lookup.entry(pixel.0) {
+            if palette.len() == colors {
+                palette.clear();
+                break;
+            }
+            entry.insert(palette.len() as u8);
+            palette.push(pixel.0);
+        }
+    }
+
+    let indices: Vec<u8> = if palette.is_empty() {
+        let rgba = image.to_rgba8();
+        let quantizer = color_quant::NeuQuant::new(10, colors, rgba.as_raw());
+        palette = quantizer
+            .color_map_rgb()
+            .chunks_exact(3)
+            .map(|color| [color[0], color[1], color[2]])
+            .collect();
+        rgba.pixels()
+            .map(|pixel| quantizer.index_of(&pixel.0) as u8)
+            .collect()
+    } else {
+        rgb.pixels().map(|pixel| lookup[&pixel.0]).collect()
+    };
+
+    let bits = match palette.len() {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        _ => 8,
+    };
+
+    // Pack the indices, rows start on a byte boundary
+    let per_byte = 8 / bits as usize;
+    let stride = (width as usize).div_ceil(per_byte);
+    let mut data = vec![0u8; stride * height as usize];
+    for (i, index) in indices.iter().enumerate() {
+        let (x, y) = (i % width as usize, i / width as usize);
+        let shift = 8 - bits as usize * (x % per_byte + 1);
+        data[y * stride + x / per_byte] |= index << shift;
+    }
+
+    let hex: String = palette
+        .iter()
+        .flatten()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    let (data, filter) = deflate(data)?;
+
+    Ok(EncodedImage {
+        width,
+        height,
+        color_space: format!("[/Indexed /DeviceRGB {} <{}>]", palette.len() - 1, hex),
+        bits_per_component: bits,
+        filter: filter.map(str::to_string),
+        decode: None,
+        decode_parms: None,
+        data,
+    })
+}
+
+/// Encode the raw samples, keeping 16 bits per component for images
+/// deeper than 8 bits
+pub fn encode_lossless(image: DynamicImage) -> Result<EncodedImage, Error> {
+    let (width, height) = image.dimensions();
+    let color_space = color_space(&image);
+    let color = image.color();
+
+    let (bits_per_component, data) = if color.bytes_per_pixel() > color.channel_count() {
+        let samples = if color.has_color() {
+            image.into_rgb16().into_raw()
+        } else {
+            image.into_luma16().into_raw()
+        };
+        // PDF samples are big endian
+        let data = samples
+            .iter()
+            .flat_map(|sample| sample.to_be_bytes())
+            .collect();
+        (16, data)
+    } else {
+        (8, to_8bit(image).into_bytes())
+    };
+
+    let (data, filter) = deflate(data)?;
+
+    Ok(EncodedImage {
+        width,
+        height,
+        color_space,
+        bits_per_component,
+        filter: filter.map(str::to_string),
+        decode: None,
+        decode_parms: None,
+        data,
+    })
+}
+
+/// Embed a JPEG file as it is
+pub fn encode_jpeg(data: Vec<u8>, info: &JpegInfo) -> EncodedImage {
+    let color_space = match info.components {
+        1 => "/DeviceGray",
+        3 => "/DeviceRGB",
+        _ => "/DeviceCMYK",
+    };
+    // Adobe CMYK JPEGs are stored inverted
+    let decode = (info.components == 4 && info.adobe).then(|| "[1 0 1 0 1 0 1 0]".to_string());
+
+    EncodedImage {
+        width: info.width,
+        height: info.height,
+        color_space: color_space.to_string(),
+        bits_per_component: 8,
+        filter: Some("/DCTDecode".to_string()),
+        decode,
+        decode_parms: None,
+        data,
+    }
+}
+
+/// Encode the alpha channel of an image as an 8 bit soft mask, or `None`
+/// when every pixel is opaque
+pub fn encode_alpha(image: &DynamicImage) -> Result<Option<EncodedImage>, Error> {
+    let (width, height) = image.dimensions();
+    let data: Vec<u8> = image.to_rgba8().pixels().map(|pixel| pixel.0[3]).collect();
+    if data.iter().all(|alpha| *alpha == u8::MAX) {
+        return Ok(None);
+    }
+
+    let (data, filter) = deflate(data)?;
+
+    Ok(Some(EncodedImage {
+        width,
+        height,
+        color_space: "/DeviceGray".to_string(),
+        bits_per_component: 8,
+        filter: filter.map(str::to_string),
+        decode: None,
+        decode_parms: None,
+        data,
+    }))
+}
+
+/// Encode a black and white image as a 1 bit mask, where black pixels
+/// (the foreground) are 1
+pub fn encode_mask(mask: &GrayImage) -> Result<EncodedImage, Error> {
+    let (width, height) = mask.dimensions();
+    // Rows start on a byte boundary
+    let stride = (width as usize).div_ceil(8);
+
+    let mut data = vec![0u8; stride * height as usize];
+    for (x, y, pixel) in mask.enumerate_pixels() {
+        if pixel.0[0] == 0 {
+            data[y as usize * stride + x as usize / 8] |= 0x80 >> (x % 8);
+        }
+    }
+
+    let (data, filter) = deflate(data)?;
+
+    Ok(EncodedImage {
+        width,
+        height,
+        color_space: "/DeviceGray".to_string(),
+        bits_per_component: 1,
+        filter: filter.map(str::to_string),
+        decode: None,
+        decode_parms: None,
+        data,
+    })
+}
@@ -0,0 +1,66 @@
+// JPEG header parsing, for embedding JPEG files without re-encoding
+
+/// What a PDF needs to know about a JPEG stream
+pub struct JpegInfo {
+    pub width: u32,
+    pub height: u32,
+    /// 1 (gray), 3 (RGB) or 4 (CMYK)
+    pub components: u8,
+    /// Adobe APP14 marker, CMYK data is then stored inverted
+    pub adobe: bool,
+}
+
+/// Read the header of a JPEG file.
+/// Returns `None` for anything PDF readers can't take as `/DCTDecode`,
+/// such as arithmetic coding or 12 bit samples.
+pub fn read_info(data: &[u8]) -> Option<JpegInfo> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut pos = 2;
+    let mut adobe = false;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        // Fill bytes
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let segment = data.get(pos + 4..pos + 2 + length)?;
+
+        match marker {
+            // APP14
+            0xEE if segment.starts_with(b"Adobe") => adobe = true,
+            // Baseline, extended and progressive Huffman frames
+            0xC0..=0xC2 => {
+                if segment.len() < 6 || segment[0] != 8 {
+                    return None;
+                }
+                let components = segment[5];
+                if !matches!(components, 1 | 3 | 4) {
+                    return None;
+                }
+
+                return Some(JpegInfo {
+                    height: u16::from_be_bytes([segment[1], segment[2]]) as u32,
+                    width: u16::from_be_bytes([segment[3], segment[4]]) as u32,
+                    components,
+                    adobe,
+                });
+            }
+            // Other frame types
+            0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => return None,
+            _ => {}
+        }
+
+        pos += 2 + length;
+    }
+
+    None
+}
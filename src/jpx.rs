@@ -0,0 +1,136 @@
+// JPEG 2000 encoding, embedded as /JPXDecode
+
+use std::ffi::c_void;
+use std::io::{Cursor, Seek, SeekFrom, Write};
+
+use image::{DynamicImage, GenericImageView};
+use openjp2::openjpeg::*;
+use openjp2::{opj_image, opj_image_comptparm};
+
+use crate::image::{to_8bit, EncodedImage};
+use crate::Error;
+
+/// Encode an image as a JPEG 2000 codestream. The quality (1-100) sets the
+/// target PSNR, from 20 dB up to 45 dB.
+pub fn encode_image(image: DynamicImage, quality: u8) -> Result<EncodedImage, Error> {
+    let (width, height) = image.dimensions();
+    let image = to_8bit(image);
+    let components = if image.color().has_color() { 3 } else { 1 };
+
+    let params = opj_image_comptparm {
+        dx: 1,
+        dy: 1,
+        w: width,
+        h: height,
+        x0: 0,
+        y0: 0,
+        prec: 8,
+        bpp: 8,
+        sgnd: 0,
+    };
+    let color_space = if components == 3 {
+        OPJ_CLRSPC_SRGB
+    } else {
+        OPJ_CLRSPC_GRAY
+    };
+    let mut j2k_image = opj_image::create(&vec![params; components], color_space)
+        .ok_or_else(|| encode_error("could not allocate image"))?;
+    j2k_image.x1 = width;
+    j2k_image.y1 = height;
+
+    // Split the interleaved samples into planes
+    let samples = image.as_bytes();
+    if let Some(planes) = j2k_image.comps_data_mut_iter() {
+        for (c, plane) in planes.enumerate() {
+            for (value, sample) in plane
+                .iter_mut()
+                .zip(samples.iter().skip(c).step_by(components))
+            {
+                *value = *sample as i32;
+            }
+        }
+    }
+
+    let mut distortion = [0.0; 100];
+    distortion[0] = 20.0 + quality.min(100) as f32 / 4.0;
+    // Every resolution level halves the image, stop before it vanishes
+    let levels = 32 - width.min(height).max(1).leading_zeros();
+    let mut parameters = opj_cparameters_t {
+        tcp_numlayers: 1,
+        cp_fixed_quality: 1,
+        tcp_distoratio: distortion,
+        irreversible: 1,
+        tcp_mct: (components == 3) as i8,
+        numresolution: (levels as i32).min(6),
+        ..Default::default()
+    };
+
+    let mut output = Box::new(Cursor::new(Vec::new()));
+    let image_ptr = Box::into_raw(j2k_image);
+    // SAFETY: every pointer comes from openjp2 or a live Box, and the
+    // output outlives the stream writing into it
+    let encoded = unsafe {
+        let codec = opj_create_compress(OPJ_CODEC_J2K);
+        let stream = opj_stream_default_create(0);
+        opj_stream_set_write_function(stream, Some(write_output));
+        opj_stream_set_seek_function(stream, Some(seek_output));
+        opj_stream_set_skip_function(stream, Some(skip_output));
+        opj_stream_set_user_data(stream, &mut *output as *mut _ as *mut c_void, None);
+
+        let encoded = opj_setup_encoder(codec, &mut parameters, image_ptr) != 0
+            && opj_start_compress(codec, image_ptr, stream) != 0
+            && opj_encode(codec, stream) != 0
+            && opj_end_compress(codec, stream) != 0;
+
+        // Destroying the stream flushes what is left of it
+        opj_stream_destroy(stream);
+        opj_destroy_codec(codec);
+        opj_image_destroy(image_ptr);
+        encoded
+    };
+    if !encoded {
+        return Err(encode_error("encoding failed"));
+    }
+
+    Ok(EncodedImage {
+        width,
+        height,
+        color_space: if components == 3 {
+            "/DeviceRGB".to_string()
+        } else {
+            "/DeviceGray".to_string()
+        },
+        bits_per_component: 8,
+        filter: Some("/JPXDecode".to_string()),
+        decode: None,
+        decode_parms: None,
+        data: output.into_inner(),
+    })
+}
+
+fn encode_error(message: &str) -> Error {
+    Error::Io(std::io::Error::other(format!("JPEG 2000: {}", message)))
+}
+
+unsafe extern "C" fn write_output(
+    buffer: *mut c_void,
+    length: usize,
+    output: *mut c_void,
+) -> usize {
+    let output = &mut *(output as *mut Cursor<Vec<u8>>);
+    let buffer = std::slice::from_raw_parts(buffer as *const u8, length);
+    output.write(buffer).unwrap_or(usize::MAX)
+}
+
+unsafe extern "C" fn seek_output(offset: i64, output: *mut c_void) -> i32 {
+    let output = &mut *(output as *mut Cursor<Vec<u8>>);
+    output.seek(SeekFrom::Start(offset as u64)).is_ok() as i32
+}
+
+unsafe extern "C" fn skip_output(offset: i64, output: *mut c_void) -> i64 {
+    let output = &mut *(output as *mut Cursor<Vec<u8>>);
+    match output.seek(SeekFrom::Current(offset)) {
+        Ok(_) => offset,
+        Err(_) => -1,
+    }
+}
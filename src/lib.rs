@@ -21,15 +21,34 @@
 
 use std::{fs::File, path::Path};
 
+#[cfg(feature = "tokio")]
+mod async_pdf;
+mod chunks;
 mod config;
 mod error;
+mod frames;
 mod image;
+mod jpeg;
+#[cfg(feature = "jpeg2000")]
+mod jpx;
+mod mrc;
 mod pdf;
+mod png;
+mod reader;
+mod split;
+mod strips;
+mod writer;
 
 // Re-export
+#[cfg(feature = "tokio")]
+pub use async_pdf::AsyncPDF;
+#[cfg(feature = "stream")]
+pub use chunks::ChunkStream;
+pub use chunks::Chunks;
 pub use config::*;
 pub use error::Error;
-pub use pdf::PDF;
+pub use pdf::{PageInfo, PDF};
+pub use split::{Split, SplitPDF};
 
 /// An alias of `scannedpdf::PDF::create_file`
 pub fn create<P: AsRef<Path>>(
@@ -39,3 +58,24 @@ pub fn create<P: AsRef<Path>>(
 ) -> std::io::Result<pdf::PDF<std::fs::File>> {
     pdf::PDF::create(File::create(path)?, default_page_config, total_pages)
 }
+
+/// Merge PDF files written by `scannedpdf` into a new one, without
+/// re-encoding their images. The outlines of each file are nested under an
+/// entry titled with its file name.
+pub fn merge<P: AsRef<Path>, Q: AsRef<Path>>(path: P, inputs: &[Q]) -> Result<(), Error> {
+    let mut total_pages = 0;
+    for input in inputs {
+        total_pages += reader::Document::open(File::open(input)?)?.pages()?.len();
+    }
+
+    let mut pdf = create(path, PageConfig::new(), total_pages)?;
+    for input in inputs {
+        let title = input
+            .as_ref()
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned());
+        pdf.add_pages_from_pdf(File::open(input)?, title)?;
+    }
+    pdf.finish()?;
+    Ok(())
+}
@@ -0,0 +1,149 @@
+// Mixed Raster Content
+//
+// A page is split into a black and white mask at full resolution, holding
+// the sharp edges of text and line art, a low resolution background and a
+// low resolution foreground giving the mask its colour.
+
+use image::{DynamicImage, GrayImage, Rgb, RgbImage};
+
+use crate::image::{encode_image, encode_mask, sauvola, EncodedImage};
+use crate::{Error, PageConfig};
+
+/// Background is stored at 1/n of the page resolution
+const BACKGROUND_SCALE: u32 = 3;
+/// Foreground is stored at 1/n of the page resolution
+const FOREGROUND_SCALE: u32 = 6;
+
+pub struct Layers {
+    pub background: EncodedImage,
+    /// 1 bit mask, 1 where the foreground is painted
+    pub mask: EncodedImage,
+    pub foreground: EncodedImage,
+}
+
+pub fn split(image: &DynamicImage, page_config: &PageConfig) -> Result<Layers, Error> {
+    // Black pixels of the mask are foreground
+    let mask = sauvola(&image.to_luma8(), 25, 0.3);
+    let rgb = image.to_rgb8();
+
+    // Keep the blurry edges of the foreground out of the background
+    let background = layer(&rgb, &dilate(&mask), false, BACKGROUND_SCALE);
+    let foreground = layer(&rgb, &mask, true, FOREGROUND_SCALE);
+
+    let (background, foreground) = if image.color().has_color() {
+        (
+            DynamicImage::ImageRgb8(background),
+            DynamicImage::ImageRgb8(foreground),
+        )
+    } else {
+        (
+            DynamicImage::ImageLuma8(DynamicImage::ImageRgb8(background).into_luma8()),
+            DynamicImage::ImageLuma8(DynamicImage::ImageRgb8(foreground).into_luma8()),
+        )
+    };
+
+    Ok(Layers {
+        background: encode_image(background, page_config.quality, page_config)?,
+        mask: encode_mask(&mask)?,
+        foreground: encode_image(foreground, page_config.quality, page_config)?,
+    })
+}
+
+/// Grow the black pixels of a mask by one pixel
+fn dilate(mask: &GrayImage) -> GrayImage {
+    let (width, height) = mask.dimensions();
+
+    GrayImage::from_fn(width, height, |x, y| {
+        let (x0, y0) = (x.saturating_sub(1), y.saturating_sub(1));
+        let (x1, y1) = ((x + 1).min(width - 1), (y + 1).min(height - 1));
+
+        let black = (y0..=y1).any(|sy| (x0..=x1).any(|sx| mask.get_pixel(sx, sy).0[0] == 0));
+        image::Luma([if black { 0 } else { 255 }])
+    })
+}
+
+/// Average colour of the foreground (or background) pixels in each
+/// `scale` x `scale` block. Blocks without any are filled in from their
+/// neighbours, so the layer stays smooth and compresses well.
+fn layer(rgb: &RgbImage, mask: &GrayImage, foreground: bool, scale: u32) -> RgbImage {
+    let (width, height) = rgb.dimensions();
+    let (layer_width, layer_height) = (width.div_ceil(scale), height.div_ceil(scale));
+    let index = |x: u32, y: u32| (y * layer_width + x) as usize;
+
+    let mut sums = vec![[0u64; 4]; (layer_width * layer_height) as usize];
+    for (x, y, pixel) in rgb.enumerate_pixels() {
+        if (mask.get_pixel(x, y).0[0] == 0) == foreground {
+            let sum = &mut sums[index(x / scale, y / scale)];
+            for (total, value) in sum.iter_mut().zip(pixel.0) {
+                *total += value as u64;
+            }
+            sum[3] += 1;
+        }
+    }
+
+    let mut blocks: Vec<Option<[u8; 3]>> = sums
+        .iter()
+        .map(|sum| {
+            (sum[3] > 0).then(|| {
+                [
+                    (sum[0] / sum[3]) as u8,
+                    (sum[1] / sum[3]) as u8,
+                    (sum[2] / sum[3]) as u8,
+                ]
+            })
+        })
+        .collect();
+
+    // Grow the known blocks into the unknown ones, one ring at a time
+    let mut filled = Vec::new();
+    loop {
+        for y in 0..layer_height {
+            for x in 0..layer_width {
+                if blocks[index(x, y)].is_some() {
+                    continue;
+                }
+
+                let mut sum = [0u32; 3];
+                let mut count = 0;
+                for (nx, ny) in [
+                    (x.wrapping_sub(1), y),
+                    (x + 1, y),
+                    (x, y.wrapping_sub(1)),
+                    (x, y + 1),
+                ] {
+                    if nx >= layer_width || ny >= layer_height {
+                        continue;
+                    }
+                    if let Some(neighbour) = blocks[index(nx, ny)] {
+                        for (total, value) in sum.iter_mut().zip(neighbour) {
+                            *total += value as u32;
+                        }
+                        count += 1;
+                    }
+                }
+
+                if count > 0 {
+                    filled.push((index(x, y), sum.map(|total| (total / count) as u8)));
+                }
+            }
+        }
+
+        if filled.is_empty() {
+            break;
+        }
+        for (i, color) in filled.drain(..) {
+            blocks[i] = Some(color);
+        }
+    }
+
+    // Nothing to grow from: the layer is never visible anyway
+    let fallback = if foreground {
+        [0, 0, 0]
+    } else {
+        [255, 255, 255]
+    };
+
+    RgbImage::from_fn(layer_width, layer_height, |x, y| {
+        Rgb(blocks[index(x, y)].unwrap_or(fallback))
+    })
+}
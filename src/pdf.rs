@@ -1,31 +1,93 @@
 use image::{DynamicImage, GenericImageView};
-use std::io::Write;
+use std::io::{Read, SeekFrom, Write};
 use std::path::Path;
-use std::{collections::HashMap, io::Seek};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Seek,
+};
 
-use crate::{Error, PageConfig};
+use crate::writer::CountingWriter;
+
+use crate::image::EncodedImage;
+use crate::{BlankPage, Encoding, Error, PageConfig, Target};
 
 fn encode_unicode_string(s: &str) -> Vec<u8> {
-    let mut encoded = vec![0xFE, 0xFF];
-    encoded.extend(s.encode_utf16().flat_map(|c| vec![(c >> 8) as u8, c as u8]));
+    let mut encoded = Vec::new();
+    for byte in [0xFE, 0xFF]
+        .into_iter()
+        .chain(s.encode_utf16().flat_map(|c| [(c >> 8) as u8, c as u8]))
+    {
+        // Bytes that would end the literal string, or be read as an end of line
+        match byte {
+            b'(' | b')' | b'\\' => encoded.extend([b'\\', byte]),
+            b'\r' => encoded.extend(b"\\r"),
+            _ => encoded.push(byte),
+        }
+    }
     encoded
 }
 
+/// Information about a page added to the PDF
+#[derive(Debug, Default, Clone)]
+pub struct PageInfo {
+    /// Skew in degrees (clockwise) detected and corrected by deskewing
+    pub skew: Option<f32>,
+    /// Ink coverage measured by blank page detection
+    pub ink: Option<f32>,
+    /// The page was detected as blank
+    pub blank: bool,
+    /// The page was blank and left out of the PDF
+    pub skipped: bool,
+    /// JPEG quality picked for the page's target
+    pub quality: Option<u8>,
+}
+
+/// An outline entry and the entries nested under it
+#[derive(Clone)]
+pub(crate) struct Outline {
+    /// Object ID of the page it points at
+    pub page: usize,
+    pub title: String,
+    pub children: Vec<Outline>,
+}
+
+impl Outline {
+    /// Number of entries nested under this one, at any depth
+    fn descendants(&self) -> usize {
+        self.children
+            .iter()
+            .map(|child| 1 + child.descendants())
+            .sum()
+    }
+}
+
+/// The image of a page
+pub(crate) enum Source {
+    /// Decoded pixels, with the ICC profile they are in
+    Decoded(DynamicImage, Option<Vec<u8>>),
+    /// Compressed data embedded as it is, with its ICC profile
+    Encoded(EncodedImage, Option<Vec<u8>>),
+}
+
 /// A PDF file
 ///
-pub struct PDF<W: Write + Seek> {
+pub struct PDF<W: Write> {
     default_page_config: PageConfig,
     // writer: W,
-    writer: std::io::BufWriter<W>,
-    current_page: usize,
+    writer: CountingWriter<W>,
     total_pages: usize,
+    // Object IDs of the pages
+    pages: Vec<usize>,
+    next_object: usize,
     // outlines: HashMap<usize, String>,
-    outlines: Vec<(usize, String)>,
+    outlines: Vec<Outline>,
     xref_offset: usize,
     object_offsets: HashMap<usize, usize>,
+    // Offset of the previous xref table, when appending to a file
+    prev_xref: Option<usize>,
 }
 
-impl<W: Write + Seek> PDF<W> {
+impl<W: Write> PDF<W> {
     pub fn create(
         writer: W,
         default_page_config: PageConfig,
@@ -33,20 +95,29 @@ impl<W: Write + Seek> PDF<W> {
     ) -> std::io::Result<Self> {
         let mut pdf = PDF {
             default_page_config,
-            writer: std::io::BufWriter::new(writer),
-            current_page: 0,
+            writer: CountingWriter::new(writer, 0),
             xref_offset: 0,
             total_pages,
+            pages: Vec::new(),
+            // 1, 2 and 3 are the catalog, pages and outlines
+            next_object: 4,
             outlines: Vec::new(),
             object_offsets: HashMap::new(),
+            prev_xref: None,
         };
         pdf.write_top()?;
         Ok(pdf)
     }
 
+    /// Reserve the next object ID
+    fn new_object(&mut self) -> usize {
+        self.next_object += 1;
+        self.next_object - 1
+    }
+
     fn obj_start(&mut self, i: usize) -> std::io::Result<()> {
         // Remember the offset of the object
-        let offset = self.writer.seek(std::io::SeekFrom::Current(0))?;
+        let offset = self.writer.position();
         self.object_offsets.insert(i, offset as usize);
         self.writer.write_all(format!("{} 0 obj\n", i).as_bytes())?;
         self.writer.write_all(b"<<\n")?;
@@ -70,48 +141,65 @@ impl<W: Write + Seek> PDF<W> {
         self.writer.write_all(b"/Outlines 3 0 R\n")?;
         self.writer.write_all(b"/PageMode /UseOutlines\n")?;
         self.obj_end()?;
-        // Pages Object
-        self.write_pages()?;
         self.writer.flush()?;
         Ok(())
     }
 
     fn write_outlines(&mut self) -> std::io::Result<()> {
+        let outlines = std::mem::take(&mut self.outlines);
+        let count: usize = outlines.iter().map(|item| 1 + item.descendants()).sum();
+        let (first, last) = self.write_outline_items(&outlines, 3)?;
+
         self.obj_start(3)?;
         self.writer.write_all(b"/Type /Outlines\n")?;
-        self.writer.write_all(b"/Count ")?;
-
-        let count = self.outlines.len();
-        let outlines = self.outlines.clone();
-
-        self.writer.write_all(format!("{}", count).as_bytes())?;
-
-        let start = self.total_objects() - count + 1; // ID of the first outline
-
-        if count > 0 {
+        self.writer
+            .write_all(format!("/Count {}\n", count).as_bytes())?;
+        if !outlines.is_empty() {
             self.writer
-                .write_all(format!("/First {} 0 R\n", start).as_bytes())?;
+                .write_all(format!("/First {} 0 R\n", first).as_bytes())?;
             self.writer
-                .write_all(format!("/Last {} 0 R\n", self.total_objects()).as_bytes())?;
+                .write_all(format!("/Last {} 0 R\n", last).as_bytes())?;
         }
         self.obj_end()?;
 
-        for (i, (page_id, title)) in outlines.iter().enumerate() {
+        self.outlines = outlines;
+        Ok(())
+    }
+
+    /// Write the entries of one level of the outline tree, returns the IDs
+    /// of the first and the last one
+    fn write_outline_items(
+        &mut self,
+        items: &[Outline],
+        parent: usize,
+    ) -> std::io::Result<(usize, usize)> {
+        let count = items.len();
+        let start = self.next_object; // ID of the first outline
+        self.next_object += count;
+
+        for (i, item) in items.iter().enumerate() {
+            let children = if item.children.is_empty() {
+                None
+            } else {
+                Some(self.write_outline_items(&item.children, start + i)?)
+            };
+
             self.obj_start(start + i)?;
             self.writer.write_all(b"/Title (")?;
             // self.writer.write_all(format!("({})", title).as_bytes())?;
             // /Title (\xFE\xFF...)
             // Encode the title as UTF-16BE
-            self.writer.write_all(&encode_unicode_string(title))?;
+            self.writer.write_all(&encode_unicode_string(&item.title))?;
             self.writer.write_all(b")\n")?;
 
             self.writer.write_all(b"\n")?;
 
-            self.writer.write_all(b"/Parent 3 0 R\n")?;
+            self.writer
+                .write_all(format!("/Parent {} 0 R\n", parent).as_bytes())?;
 
             self.writer.write_all(b"/Dest ")?;
             self.writer
-                .write_all(format!("[{} 0 R /XYZ 0 0 0]", page_id).as_bytes())?;
+                .write_all(format!("[{} 0 R /XYZ 0 0 0]\n", item.page).as_bytes())?;
 
             if i > 0 {
                 self.writer
@@ -121,11 +209,20 @@ impl<W: Write + Seek> PDF<W> {
                 self.writer
                     .write_all(format!("/Next {} 0 R\n", start + i + 1).as_bytes())?;
             }
+            // Open, showing its children
+            if let Some((first, last)) = children {
+                self.writer
+                    .write_all(format!("/First {} 0 R\n", first).as_bytes())?;
+                self.writer
+                    .write_all(format!("/Last {} 0 R\n", last).as_bytes())?;
+                self.writer
+                    .write_all(format!("/Count {}\n", item.descendants()).as_bytes())?;
+            }
 
             self.obj_end()?;
         }
 
-        Ok(())
+        Ok((start, start + count.saturating_sub(1)))
     }
 
     fn write_pages(&mut self) -> std::io::Result<()> {
@@ -140,13 +237,14 @@ impl<W: Write + Seek> PDF<W> {
         // Remember current position
         // self.count_offset = self.writer.seek(std::io::SeekFrom::Current(0))? as usize;
         // self.writer.write_all(b"000000\n")?;
+        // Only pages actually added, skipped pages leave no gaps
         self.writer
-            .write_all(self.total_pages.to_string().as_bytes())?;
+            .write_all(format!("{}\n", self.pages.len()).as_bytes())?;
 
         self.writer.write_all(b"/Kids [\n")?;
-        for i in 0..self.total_pages {
+        for page in &self.pages {
             self.writer
-                .write_all(format!("{} 0 R\n", i * 3 + 4).as_bytes())?;
+                .write_all(format!("{} 0 R\n", page).as_bytes())?;
         }
 
         self.writer.write_all(b"]\n")?;
@@ -158,17 +256,38 @@ impl<W: Write + Seek> PDF<W> {
 
     fn write_xref(&mut self) -> std::io::Result<()> {
         // Remember current position
-        self.xref_offset = self.writer.seek(std::io::SeekFrom::Current(0))? as usize;
+        self.xref_offset = self.writer.position() as usize;
         self.writer.write_all(b"xref\n")?;
+
+        if self.prev_xref.is_some() {
+            // Only the objects of the update, in runs of consecutive numbers
+            let mut ids: Vec<usize> = self.object_offsets.keys().copied().collect();
+            ids.sort_unstable();
+            for run in ids.chunk_by(|a, b| a + 1 == *b) {
+                self.writer
+                    .write_all(format!("{} {}\n", run[0], run.len()).as_bytes())?;
+                for id in run {
+                    let offset = self.object_offsets[id];
+                    self.writer
+                        .write_all(format!("{:010} 00000 n \n", offset).as_bytes())?;
+                }
+            }
+            return Ok(());
+        }
+
         self.writer
-            .write_all(format!("0 {}\n", self.total_objects()).as_bytes())?;
+            .write_all(format!("0 {}\n", self.total_objects() + 1).as_bytes())?;
 
-        self.writer.write_all(b"0000000000 65535 f\n")?;
+        // Entries are exactly 20 bytes, including a two byte end of line
+        self.writer.write_all(b"0000000000 65535 f \n")?;
 
-        for i in 1..self.total_objects() {
-            let offset = self.object_offsets.get(&i).unwrap();
+        for i in 1..=self.total_objects() {
+            let offset = self
+                .object_offsets
+                .get(&i)
+                .ok_or_else(|| std::io::Error::other(format!("object {} was never written", i)))?;
             self.writer
-                .write_all(format!("{:010} 00000 n\n", offset).as_bytes())?;
+                .write_all(format!("{:010} 00000 n \n", offset).as_bytes())?;
         }
         Ok(())
     }
@@ -177,8 +296,12 @@ impl<W: Write + Seek> PDF<W> {
         self.writer.write_all(b"trailer\n")?;
         self.writer.write_all(b"<<\n")?;
         self.writer
-            .write_all(format!("/Size {}\n", self.total_objects()).as_bytes())?;
+            .write_all(format!("/Size {}\n", self.total_objects() + 1).as_bytes())?;
         self.writer.write_all(b"/Root 1 0 R\n")?;
+        if let Some(prev_xref) = self.prev_xref {
+            self.writer
+                .write_all(format!("/Prev {}\n", prev_xref).as_bytes())?;
+        }
         self.writer.write_all(b">>\n")?;
         self.writer.write_all(b"startxref\n")?;
         self.writer
@@ -190,37 +313,71 @@ impl<W: Write + Seek> PDF<W> {
     fn write_image_obj(
         &mut self,
         index: usize,
-        image: DynamicImage,
-        quality: u8,
+        image: &EncodedImage,
+        smask: Option<usize>,
     ) -> Result<(), Error> {
-        let (width, height) = image.dimensions();
-
         self.obj_start(index)?;
         self.writer.write_all(b"/Type /XObject\n")?;
         self.writer.write_all(b"/Subtype /Image\n")?;
         self.writer.write_all(b"/Width ")?;
-        self.writer.write_all(format!("{}\n", width).as_bytes())?;
+        self.writer
+            .write_all(format!("{}\n", image.width).as_bytes())?;
         self.writer.write_all(b"/Height ")?;
-        self.writer.write_all(format!("{}\n", height).as_bytes())?;
-        self.writer.write_all(b"/ColorSpace /DeviceRGB\n")?;
-        self.writer.write_all(b"/BitsPerComponent 8\n")?;
+        self.writer
+            .write_all(format!("{}\n", image.height).as_bytes())?;
+        self.writer
+            .write_all(format!("/ColorSpace {}\n", image.color_space).as_bytes())?;
+        self.writer
+            .write_all(format!("/BitsPerComponent {}\n", image.bits_per_component).as_bytes())?;
 
-        #[cfg(feature = "flate2")]
-        {
+        if let Some(filter) = &image.filter {
             self.writer
-                .write_all(b"/Filter [/FlateDecode /DCTDecode]\n")?;
+                .write_all(format!("/Filter {}\n", filter).as_bytes())?;
         }
-        #[cfg(not(feature = "flate2"))]
-        {
-            self.writer.write_all(b"/Filter /DCTDecode\n")?;
+        if let Some(decode) = &image.decode {
+            self.writer
+                .write_all(format!("/Decode {}\n", decode).as_bytes())?;
+        }
+        if let Some(decode_parms) = &image.decode_parms {
+            self.writer
+                .write_all(format!("/DecodeParms {}\n", decode_parms).as_bytes())?;
+        }
+        if let Some(smask) = smask {
+            self.writer
+                .write_all(format!("/SMask {} 0 R\n", smask).as_bytes())?;
         }
 
         self.writer.write_all(b"/Length ")?;
+        self.writer
+            .write_all(format!("{}\n", image.data.len()).as_bytes())?;
+        self.writer.write_all(b">>\n")?;
+        self.writer.write_all(b"stream\n")?;
+        self.writer.write_all(&image.data)?;
+        self.writer.write_all(b"\nendstream\n")?;
+        self.writer.write_all(b"endobj\n")?;
+
+        Ok(())
+    }
 
-        let data = crate::image::encode_image(image, quality)?;
+    fn write_icc_obj(&mut self, index: usize, profile: &[u8], components: u8) -> Result<(), Error> {
+        let alternate = match components {
+            1 => "/DeviceGray",
+            3 => "/DeviceRGB",
+            _ => "/DeviceCMYK",
+        };
+        let (data, filter) = crate::image::deflate(profile.to_vec())?;
 
+        self.obj_start(index)?;
+        self.writer
+            .write_all(format!("/N {}\n", components).as_bytes())?;
+        self.writer
+            .write_all(format!("/Alternate {}\n", alternate).as_bytes())?;
+        if let Some(filter) = filter {
+            self.writer
+                .write_all(format!("/Filter {}\n", filter).as_bytes())?;
+        }
         self.writer
-            .write_all(format!("{}\n", data.len()).as_bytes())?;
+            .write_all(format!("/Length {}\n", data.len()).as_bytes())?;
         self.writer.write_all(b">>\n")?;
         self.writer.write_all(b"stream\n")?;
         self.writer.write_all(&data)?;
@@ -230,34 +387,195 @@ impl<W: Write + Seek> PDF<W> {
         Ok(())
     }
 
+    /// Write an object read from another file, given its dictionary and
+    /// stream data
+    fn write_copied_obj(
+        &mut self,
+        index: usize,
+        object: &[u8],
+        stream: Option<&[u8]>,
+    ) -> std::io::Result<()> {
+        let offset = self.writer.position();
+        self.object_offsets.insert(index, offset as usize);
+        self.writer
+            .write_all(format!("{} 0 obj\n", index).as_bytes())?;
+        self.writer.write_all(object)?;
+        if let Some(stream) = stream {
+            self.writer.write_all(stream)?;
+            self.writer.write_all(b"\nendstream\n")?;
+            self.writer.write_all(b"endobj\n")?;
+        }
+        Ok(())
+    }
+
     pub fn add_page_from_image(
         &mut self,
         image: image::DynamicImage,
         outline: Option<String>,
         page_config: Option<PageConfig>,
-    ) -> Result<(), Error> {
-        if self.current_page >= self.total_pages {
+    ) -> Result<PageInfo, Error> {
+        self.add_page(Source::Decoded(image, None), outline, page_config.as_ref())
+    }
+
+    fn add_page(
+        &mut self,
+        source: Source,
+        outline: Option<String>,
+        page_config: Option<&PageConfig>,
+    ) -> Result<PageInfo, Error> {
+        if self.pages.len() >= self.total_pages {
             return Err(Error::PageOverflow);
         }
 
-        let page_config = page_config.as_ref().unwrap_or(&self.default_page_config);
-
-        let image = crate::image::preprocess(image, &page_config);
+        let page_config = page_config.unwrap_or(&self.default_page_config);
+
+        let mut info = PageInfo::default();
+
+        // Image XObjects, drawn in order
+        let mut layers = Vec::new();
+        let (image_dimensions, profile) = match source {
+            Source::Decoded(image, profile) => {
+                if let BlankPage::Report(threshold) | BlankPage::Skip(threshold) =
+                    page_config.blank_page
+                {
+                    let ink = crate::image::ink_coverage(&image);
+                    info.ink = Some(ink);
+                    info.blank = ink < threshold;
+
+                    if info.blank && matches!(page_config.blank_page, BlankPage::Skip(_)) {
+                        info.skipped = true;
+                        return Ok(info);
+                    }
+                }
+
+                let image = crate::image::preprocess(image, page_config, &mut info)?;
+                let dimensions = image.dimensions();
+
+                match page_config.encoding {
+                    Encoding::Mrc => {
+                        let mrc = crate::mrc::split(&image, page_config)?;
+                        layers.push(("Bg", mrc.background, None));
+                        // The mask shapes the foreground
+                        layers.push(("Fg", mrc.foreground, Some(mrc.mask)));
+                    }
+                    _ => {
+                        // Transparency goes into a separate soft mask
+                        let smask = if image.color().has_alpha() {
+                            crate::image::encode_alpha(&image)?
+                        } else {
+                            None
+                        };
+                        let image = match page_config.encoding {
+                            Encoding::Lossless => crate::image::encode_lossless(image)?,
+                            Encoding::Indexed(colors) => {
+                                crate::image::encode_indexed(&image, colors)?
+                            }
+                            #[cfg(feature = "jpeg2000")]
+                            Encoding::Jpeg2000 => {
+                                crate::jpx::encode_image(image, page_config.quality)?
+                            }
+                            #[cfg(not(feature = "jpeg2000"))]
+                            Encoding::Jpeg2000 => {
+                                return Err(image::ImageError::Unsupported(
+                                    image::error::UnsupportedError::from_format_and_kind(
+                                        image::error::ImageFormatHint::Name(
+                                            "JPEG 2000".to_string(),
+                                        ),
+                                        image::error::UnsupportedErrorKind::GenericFeature(
+                                            "the jpeg2000 feature".to_string(),
+                                        ),
+                                    ),
+                                )
+                                .into())
+                            }
+                            _ => {
+                                let quality = match page_config.target {
+                                    Target::None => page_config.quality,
+                                    target => {
+                                        let quality = crate::image::search_quality(
+                                            &image,
+                                            target,
+                                            page_config,
+                                        )?;
+                                        info.quality = Some(quality);
+                                        quality
+                                    }
+                                };
+                                crate::image::encode_image(image, quality, page_config)?
+                            }
+                        };
+                        layers.push(("Img", image, smask));
+                    }
+                }
+
+                (dimensions, profile)
+            }
+            Source::Encoded(image, profile) => {
+                let dimensions =
+                    crate::image::display_dimensions((image.width, image.height), page_config)?;
+                layers.push(("Img", image, None));
 
-        let (page_width, page_height) = page_config.size.dimensions();
+                (dimensions, profile)
+            }
+        };
 
-        let i = self.current_page * 3 + 4;
+        let (page_width, page_height) =
+            crate::image::page_dimensions(image_dimensions, page_config)?;
+        let rotation = page_config.rotation.degrees();
 
         // Contents Object
         let mut contents = Vec::new();
         contents.write_all(b"q\n")?;
 
-        contents.extend(crate::image::get_operands(image.dimensions(), &page_config));
+        contents.extend(crate::image::get_operands(
+            image_dimensions,
+            page_config,
+            self.pages.len(),
+        )?);
 
-        contents.write_all(b"/Img Do\n")?;
+        for (name, _, _) in &layers {
+            contents.write_all(format!("/{} Do\n", name).as_bytes())?;
+        }
         contents.write_all(b"Q\n")?;
 
-        self.write_image_obj(i + 1, image, page_config.quality)?;
+        // Colours are in the space of the embedded profile, if it matches
+        if let Some(profile) = profile {
+            if let Some(components) = crate::image::icc_components(&profile) {
+                let mut id = None;
+                for (_, image, _) in &mut layers {
+                    if image.components() != Some(components) {
+                        continue;
+                    }
+                    let id = match id {
+                        Some(id) => id,
+                        None => {
+                            let new = self.new_object();
+                            self.write_icc_obj(new, &profile, components)?;
+                            *id.insert(new)
+                        }
+                    };
+                    image.color_space = format!("[/ICCBased {} 0 R]", id);
+                }
+            }
+        }
+
+        let mut xobjects = Vec::new();
+        for (name, image, smask) in &layers {
+            let smask = match smask {
+                Some(smask) => {
+                    let id = self.new_object();
+                    self.write_image_obj(id, smask, None)?;
+                    Some(id)
+                }
+                None => None,
+            };
+            let id = self.new_object();
+            self.write_image_obj(id, image, smask)?;
+            xobjects.push((name, id));
+        }
+
+        let i = self.new_object();
+        let contents_id = self.new_object();
 
         // Page Object
         self.obj_start(i)?;
@@ -270,22 +588,30 @@ impl<W: Write + Seek> PDF<W> {
             .write_all(format!("{} {}", page_width, page_height).as_bytes())?;
         self.writer.write_all(b"]\n")?;
 
+        // Rotation
+        if rotation != 0 {
+            self.writer
+                .write_all(format!("/Rotate {}\n", rotation).as_bytes())?;
+        }
+
         // Resources
         self.writer.write_all(b"/Resources <<\n")?;
         self.writer.write_all(b"/XObject <<\n")?;
-        self.writer
-            .write_all(format!("/Img {} 0 R\n", i + 1).as_bytes())?;
+        for (name, id) in &xobjects {
+            self.writer
+                .write_all(format!("/{} {} 0 R\n", name, id).as_bytes())?;
+        }
         self.writer.write_all(b">>\n")?;
         self.writer.write_all(b">>\n")?;
 
         // Contents
         self.writer.write_all(b"/Contents ")?;
         self.writer
-            .write_all(format!("{} 0 R\n", i + 2).as_bytes())?;
+            .write_all(format!("{} 0 R\n", contents_id).as_bytes())?;
 
         self.obj_end()?;
 
-        self.obj_start(i + 2)?;
+        self.obj_start(contents_id)?;
 
         self.writer
             .write_all(format!("/Length {}\n", contents.len()).as_bytes())?;
@@ -298,10 +624,14 @@ impl<W: Write + Seek> PDF<W> {
         self.writer.flush()?;
         if let Some(outline) = outline {
             // self.outlines.insert(i, outline);
-            self.outlines.push((i, outline));
+            self.outlines.push(Outline {
+                page: i,
+                title: outline,
+                children: Vec::new(),
+            });
         }
-        self.current_page += 1;
-        Ok(())
+        self.pages.push(i);
+        Ok(info)
     }
 
     pub fn add_page_from_path<P: AsRef<Path>>(
@@ -309,16 +639,181 @@ impl<W: Write + Seek> PDF<W> {
         image_path: P,
         outline: Option<String>,
         page_config: Option<PageConfig>,
-    ) -> Result<(), Error> {
-        let image = image::open(image_path)?;
-        self.add_page_from_image(image, outline, page_config)
+    ) -> Result<PageInfo, Error> {
+        let format = image::ImageFormat::from_path(&image_path).ok();
+        let data = std::fs::read(image_path)?;
+        self.add_page_from_data(data, format, outline, page_config.as_ref())
+    }
+
+    /// Add every page of a multi-page TIFF, or every frame of an animated
+    /// GIF or WebP, as consecutive pages. PDF files written by this crate
+    /// are copied with `add_pages_from_pdf`. Other files are added as a
+    /// single page. The outline points at the first page written.
+    ///
+    /// When a frame can't be decoded or added, the pages before it stay in
+    /// the PDF and `Error::Frame` tells which frame failed.
+    pub fn add_pages_from_path<P: AsRef<Path>>(
+        &mut self,
+        image_path: P,
+        outline: Option<String>,
+        page_config: Option<PageConfig>,
+    ) -> Result<Vec<PageInfo>, Error> {
+        let format = image::ImageFormat::from_path(&image_path).ok();
+        let data = std::fs::read(image_path)?;
+
+        if data.starts_with(b"%PDF-") {
+            let pages = self.add_pages_from_pdf(std::io::Cursor::new(data), outline)?;
+            return Ok(vec![PageInfo::default(); pages]);
+        }
+
+        if !crate::frames::has_frames(&data) {
+            let info = self.add_page_from_data(data, format, outline, page_config.as_ref())?;
+            return Ok(vec![info]);
+        }
+
+        let config = page_config.as_ref().unwrap_or(&self.default_page_config);
+        let passthrough = config.passthrough && !config.needs_pixels();
+        let frames = crate::frames::read_frames(data, passthrough)?;
+
+        let mut outline = outline;
+        let mut infos = Vec::new();
+        for (index, frame) in frames.enumerate() {
+            let info = frame
+                .and_then(|source| self.add_page(source, outline.clone(), page_config.as_ref()))
+                .map_err(|err| Error::Frame(index, Box::new(err)))?;
+            // The outline goes on the first page actually written
+            if !info.skipped {
+                outline = None;
+            }
+            infos.push(info);
+        }
+
+        Ok(infos)
     }
 
+    /// Copy the pages of a PDF file written by this crate, without decoding
+    /// their images. Its outlines are nested under a new entry with the
+    /// given title, or added at the top level without one. Returns the
+    /// number of pages added.
+    pub fn add_pages_from_pdf<R: Read + Seek>(
+        &mut self,
+        reader: R,
+        outline: Option<String>,
+    ) -> Result<usize, Error> {
+        let mut document = crate::reader::Document::open(reader)?;
+        let pages = document.pages()?;
+        if self.pages.len() + pages.len() > self.total_pages {
+            return Err(Error::PageOverflow);
+        }
+
+        // The pages and what they use: contents, images, masks and profiles.
+        // Everything is read before anything is written, so that a broken
+        // file leaves this PDF as it was
+        let mut objects = Vec::new();
+        let mut seen = HashSet::from([2]);
+        for &page in &pages {
+            let mut pending = vec![page];
+            while let Some(id) = pending.pop() {
+                if !seen.insert(id) {
+                    continue;
+                }
+                let (object, stream) = document.object_with_stream(id)?;
+                pending.extend(crate::reader::references(&object));
+                objects.push((id, object, stream));
+            }
+        }
+        let outlines = document.outlines()?;
+
+        // Object IDs in the file and in this PDF, pages have this PDF's tree
+        // as their parent
+        let mut ids = HashMap::from([(2, 2)]);
+        for (id, _, _) in &objects {
+            ids.insert(*id, self.new_object());
+        }
+        let outlines = remap_outlines(outlines, &ids);
+
+        for (id, object, stream) in objects {
+            let object = crate::reader::renumber(&object, |reference| {
+                ids.get(&reference).copied().unwrap_or(reference)
+            });
+            self.write_copied_obj(ids[&id], &object, stream.as_deref())?;
+        }
+        self.pages.extend(pages.iter().map(|page| ids[page]));
+
+        match (outline, pages.first()) {
+            (Some(title), Some(first)) => self.outlines.push(Outline {
+                page: ids[first],
+                title,
+                children: outlines,
+            }),
+            _ => self.outlines.extend(outlines),
+        }
+
+        self.writer.flush()?;
+        Ok(pages.len())
+    }
+
+    pub(crate) fn add_page_from_data(
+        &mut self,
+        data: Vec<u8>,
+        format: Option<image::ImageFormat>,
+        outline: Option<String>,
+        page_config: Option<&PageConfig>,
+    ) -> Result<PageInfo, Error> {
+        let config = page_config.unwrap_or(&self.default_page_config);
+        let orientation = if config.auto_rotate {
+            crate::image::read_orientation(&data).filter(|orientation| *orientation != 1)
+        } else {
+            None
+        };
+        let passthrough = config.passthrough && !config.needs_pixels() && orientation.is_none();
+
+        if passthrough {
+            let profile = crate::image::read_icc_profile(&data);
+            if let Some(jpeg) = crate::jpeg::read_info(&data) {
+                let image = crate::image::encode_jpeg(data, &jpeg);
+                return self.add_page(Source::Encoded(image, profile), outline, page_config);
+            }
+            if let Some(image) = crate::png::read_png(&data) {
+                return self.add_page(Source::Encoded(image, profile), outline, page_config);
+            }
+            if let Some(image) = crate::strips::read_tiff_strip(&data) {
+                return self.add_page(Source::Encoded(image, profile), outline, page_config);
+            }
+        }
+
+        let (mut image, profile) = crate::image::decode(&data, format)?;
+        if let Some(orientation) = orientation {
+            image = crate::image::apply_orientation(image, orientation);
+        }
+
+        self.add_page(Source::Decoded(image, profile), outline, page_config)
+    }
+
+    /// Number of pages added so far
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Number of bytes written so far
+    pub(crate) fn position(&self) -> u64 {
+        self.writer.position()
+    }
+
+    /// Number of the last object
     fn total_objects(&self) -> usize {
-        3 + self.total_pages * 3 + self.outlines.len()
+        self.next_object - 1
     }
 
-    pub fn finish(mut self) -> std::io::Result<()> {
+    pub fn finish(self) -> std::io::Result<()> {
+        self.finish_into_inner()?;
+        Ok(())
+    }
+
+    /// Finish the PDF and return the writer
+    pub(crate) fn finish_into_inner(mut self) -> std::io::Result<W> {
+        // Pages Object
+        self.write_pages()?;
         self.write_outlines()?;
         self.write_xref()?;
         self.write_trailer()?;
@@ -329,7 +824,69 @@ impl<W: Write + Seek> PDF<W> {
         //     .write_all(format!("{:06}", self.total_pages).as_bytes())?;
         // self.writer.flush()?;
         // Close the writer
-        drop(self.writer);
-        Ok(())
+        self.writer.flush()?;
+        self.writer.into_inner()
+    }
+}
+
+impl PDF<Vec<u8>> {
+    /// Take the bytes written since the last call, offsets keep counting
+    /// from the start of the file
+    pub(crate) fn take_output(&mut self) -> std::io::Result<Vec<u8>> {
+        Ok(std::mem::take(self.writer.get_mut()?))
+    }
+}
+
+/// Outlines of a copied file, pointing at the copies of its pages.
+/// Entries of pages that weren't copied are left out.
+fn remap_outlines(outlines: Vec<Outline>, ids: &HashMap<usize, usize>) -> Vec<Outline> {
+    outlines
+        .into_iter()
+        .filter_map(|outline| {
+            Some(Outline {
+                page: *ids.get(&outline.page)?,
+                title: outline.title,
+                children: remap_outlines(outline.children, ids),
+            })
+        })
+        .collect()
+}
+
+impl<W: Read + Write + Seek> PDF<W> {
+    /// Open a PDF file previously written by this crate to add up to
+    /// `total_pages` more pages. The new pages, the page tree and the
+    /// outlines are appended as an incremental update, the original bytes
+    /// are left untouched.
+    pub fn append(
+        mut file: W,
+        default_page_config: PageConfig,
+        total_pages: usize,
+    ) -> Result<Self, Error> {
+        let mut document = crate::reader::Document::open(&mut file)?;
+        let pages = document.pages()?;
+        let outlines = document.outlines()?;
+        let (size, startxref) = (document.size, document.startxref);
+        drop(document);
+
+        // The update starts on a line of its own
+        let length = file.seek(SeekFrom::End(-1))? + 1;
+        let mut last = [0u8];
+        file.read_exact(&mut last)?;
+
+        let mut pdf = PDF {
+            default_page_config,
+            writer: CountingWriter::new(file, length),
+            xref_offset: 0,
+            total_pages: pages.len() + total_pages,
+            pages,
+            next_object: size,
+            outlines,
+            object_offsets: HashMap::new(),
+            prev_xref: Some(startxref),
+        };
+        if last[0] != b'\n' {
+            pdf.writer.write_all(b"\n")?;
+        }
+        Ok(pdf)
     }
 }
@@ -0,0 +1,78 @@
+// PNG chunk parsing, for embedding PNG files without decoding them. The
+// zlib data and per-row filters of PNG are what PDF's Flate predictors read.
+
+use crate::image::EncodedImage;
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Embed the image data of a PNG file as it is.
+/// Returns `None` for interlaced or transparent images, which PDF readers
+/// can't take as they are.
+pub fn read_png(data: &[u8]) -> Option<EncodedImage> {
+    let mut chunks = data.strip_prefix(SIGNATURE)?;
+
+    let mut header = None;
+    let mut palette = None;
+    let mut idat = Vec::new();
+    while chunks.len() >= 12 {
+        let length = u32::from_be_bytes(chunks[..4].try_into().ok()?) as usize;
+        let kind = &chunks[4..8];
+        let body = chunks.get(8..8 + length)?;
+
+        match kind {
+            b"IHDR" if body.len() == 13 => header = Some(body),
+            b"PLTE" => palette = Some(body),
+            b"tRNS" => return None,
+            b"IDAT" => idat.extend(body),
+            b"IEND" => break,
+            _ => {}
+        }
+
+        // Skip the CRC
+        chunks = chunks.get(8 + length + 4..)?;
+    }
+
+    let header = header?;
+    let width = u32::from_be_bytes(header[0..4].try_into().ok()?);
+    let height = u32::from_be_bytes(header[4..8].try_into().ok()?);
+    let (bits, color_type, interlace) = (header[8], header[9], header[12]);
+    if interlace != 0 || idat.is_empty() {
+        return None;
+    }
+
+    let (color_space, colors) = match color_type {
+        0 => ("/DeviceGray".to_string(), 1),
+        2 => ("/DeviceRGB".to_string(), 3),
+        3 => {
+            let palette = palette?;
+            let entries = palette.len() / 3;
+            if entries == 0 {
+                return None;
+            }
+            let hex: String = palette[..entries * 3]
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            (
+                format!("[/Indexed /DeviceRGB {} <{}>]", entries - 1, hex),
+                1,
+            )
+        }
+        // Alpha channels need decoding into a soft mask
+        _ => return None,
+    };
+
+    Some(EncodedImage {
+        width,
+        height,
+        color_space,
+        bits_per_component: bits,
+        filter: Some("/FlateDecode".to_string()),
+        decode: None,
+        decode_parms: Some(format!(
+            "<< /Predictor 15 /Colors {} /BitsPerComponent {} /Columns {} >>",
+            colors, bits, width
+        )),
+        data: idat,
+    })
+}
@@ -0,0 +1,430 @@
+// Reading back PDF files written by this crate, just enough to add to them.
+// Objects are located through the xref tables, and only the few objects
+// needed are read whole. Streams are skipped over.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+
+use crate::pdf::Outline;
+use crate::Error;
+
+/// A PDF file written by this crate
+pub struct Document<R: Read + Seek> {
+    reader: BufReader<R>,
+    /// Latest offset of each object
+    offsets: HashMap<usize, usize>,
+    /// One more than the highest object number in use
+    pub size: usize,
+    /// Offset of the last xref table
+    pub startxref: usize,
+    /// Size of the file
+    length: u64,
+}
+
+impl<R: Read + Seek> Document<R> {
+    pub fn open(reader: R) -> Result<Self, Error> {
+        let mut reader = BufReader::new(reader);
+
+        // The offset of the last xref table is at the very end
+        let length = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(length.saturating_sub(64)))?;
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail)?;
+        let start = find(&tail, b"startxref").ok_or(Error::InvalidPdf)?;
+        let startxref = first_number(&tail[start + 9..]).ok_or(Error::InvalidPdf)?;
+
+        let mut document = Document {
+            reader,
+            offsets: HashMap::new(),
+            size: 0,
+            startxref,
+            length,
+        };
+
+        // Newer sections come first and win
+        let mut next = Some(startxref);
+        let mut sections = 0;
+        while let Some(offset) = next {
+            let trailer = document.read_xref(offset)?;
+            if sections == 0 {
+                document.size = number_after(&trailer, b"/Size").ok_or(Error::InvalidPdf)?;
+                // Objects are laid out the way `PDF` writes them
+                if number_after(&trailer, b"/Root") != Some(1) {
+                    return Err(Error::InvalidPdf);
+                }
+            }
+            next = number_after(&trailer, b"/Prev");
+            sections += 1;
+            // Loops in broken files
+            if sections > 10_000 {
+                return Err(Error::InvalidPdf);
+            }
+        }
+
+        // Files of older versions of this crate leave the last object out
+        // of the xref table, and their `/Size` too
+        let (headers, highest) = document.scan()?;
+        for (id, offset) in headers {
+            document.offsets.entry(id).or_insert(offset);
+        }
+        document.size = document.size.max(highest + 1);
+
+        let catalog = document.object(1)?;
+        if number_after(&catalog, b"/Pages") != Some(2)
+            || number_after(&catalog, b"/Outlines") != Some(3)
+        {
+            return Err(Error::InvalidPdf);
+        }
+
+        Ok(document)
+    }
+
+    /// Read an xref table and return its trailer dictionary
+    fn read_xref(&mut self, offset: usize) -> Result<Vec<u8>, Error> {
+        self.reader.seek(SeekFrom::Start(offset as u64))?;
+        if self.line()?.trim_ascii() != b"xref" {
+            return Err(Error::InvalidPdf);
+        }
+
+        loop {
+            let line = self.line()?;
+            let line = line.trim_ascii();
+            if line.starts_with(b"trailer") {
+                break;
+            }
+
+            // Subsection: first object number and count
+            let mut header = line.split(|byte| *byte == b' ').filter_map(parse_number);
+            let (first, count) = match (header.next(), header.next()) {
+                (Some(first), Some(count)) => (first, count),
+                _ => return Err(Error::InvalidPdf),
+            };
+            // Entries are 20 bytes (19 in older versions of this crate),
+            // more than the rest of the file can't be
+            let position = self.reader.stream_position()?;
+            if count as u64 > self.length.saturating_sub(position) / 19 {
+                return Err(Error::InvalidPdf);
+            }
+            let last = first.checked_add(count).ok_or(Error::InvalidPdf)?;
+            for id in first..last {
+                let entry = self.line()?;
+                if entry.trim_ascii_end().ends_with(b"n") {
+                    let offset = first_number(&entry).ok_or(Error::InvalidPdf)?;
+                    self.offsets.entry(id).or_insert(offset);
+                }
+            }
+        }
+
+        let mut trailer = Vec::new();
+        loop {
+            let line = self.line()?;
+            if line.is_empty() || line.starts_with(b"startxref") {
+                break;
+            }
+            trailer.extend(line);
+        }
+        Ok(trailer)
+    }
+
+    /// Walk through every object of the file, skipping streams. Returns
+    /// the offsets of the objects, the latest of each number, and the
+    /// highest object number defined or referenced.
+    fn scan(&mut self) -> Result<(HashMap<usize, usize>, usize), Error> {
+        let mut headers = HashMap::new();
+        let mut highest = 0;
+
+        self.reader.seek(SeekFrom::Start(0))?;
+        loop {
+            let offset = self.reader.stream_position()? as usize;
+            let line = self.line()?;
+            if line.is_empty() {
+                break;
+            }
+            let trimmed = line.trim_ascii();
+            // The header, end of file markers and blank lines
+            if trimmed.is_empty() || trimmed.starts_with(b"%") {
+                continue;
+            }
+            // An xref table and its trailer, up to the end of file marker
+            if trimmed == b"xref" {
+                loop {
+                    let line = self.line()?;
+                    if line.is_empty() || line.starts_with(b"%%EOF") {
+                        break;
+                    }
+                }
+                continue;
+            }
+
+            let id = trimmed
+                .strip_suffix(b" 0 obj")
+                .and_then(parse_number)
+                .ok_or(Error::InvalidPdf)?;
+            headers.insert(id, offset);
+            highest = highest.max(id);
+
+            let mut object = line;
+            object.extend(self.read_object()?);
+            highest = references(&object).into_iter().fold(highest, usize::max);
+
+            if object.ends_with(b"stream\n") && !object.ends_with(b"endstream\n") {
+                let length = number_after(&object, b"/Length").ok_or(Error::InvalidPdf)?;
+                self.check_length(length)?;
+                self.reader.seek_relative(length as i64)?;
+                loop {
+                    let line = self.line()?;
+                    if line.is_empty() {
+                        return Err(Error::InvalidPdf);
+                    }
+                    if line.trim_ascii() == b"endobj" {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok((headers, highest))
+    }
+
+    /// Fail if a stream of `length` bytes doesn't fit in the rest of the
+    /// file
+    fn check_length(&mut self, length: usize) -> Result<(), Error> {
+        let position = self.reader.stream_position()?;
+        if length as u64 > self.length.saturating_sub(position) {
+            return Err(Error::InvalidPdf);
+        }
+        Ok(())
+    }
+
+    fn line(&mut self) -> Result<Vec<u8>, Error> {
+        let mut line = Vec::new();
+        self.reader.read_until(b'\n', &mut line)?;
+        Ok(line)
+    }
+
+    /// The dictionary of an object, without any stream
+    pub fn object(&mut self, id: usize) -> Result<Vec<u8>, Error> {
+        let offset = *self.offsets.get(&id).ok_or(Error::InvalidPdf)?;
+        self.reader.seek(SeekFrom::Start(offset as u64))?;
+        self.read_object()
+    }
+
+    /// Lines from the current position to the end of an object, or to the
+    /// start of its stream
+    fn read_object(&mut self) -> Result<Vec<u8>, Error> {
+        let mut object = Vec::new();
+        loop {
+            let line = self.line()?;
+            let done = line.is_empty()
+                || line.starts_with(b"stream")
+                || line.trim_ascii_end().ends_with(b"endobj");
+            object.extend(line);
+            if done {
+                return Ok(object);
+            }
+        }
+    }
+
+    /// The dictionary of an object without its first line, and the data
+    /// of its stream. Objects without a stream are returned whole.
+    pub fn object_with_stream(&mut self, id: usize) -> Result<(Vec<u8>, Option<Vec<u8>>), Error> {
+        let object = self.object(id)?;
+        let start = object
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .ok_or(Error::InvalidPdf)?
+            + 1;
+        let object = object[start..].to_vec();
+        if !object.ends_with(b"stream\n") || object.ends_with(b"endstream\n") {
+            return Ok((object, None));
+        }
+
+        let length = number_after(&object, b"/Length").ok_or(Error::InvalidPdf)?;
+        self.check_length(length)?;
+        let mut stream = vec![0; length];
+        self.reader.read_exact(&mut stream)?;
+        Ok((object, Some(stream)))
+    }
+
+    /// Object numbers of the pages, in order
+    pub fn pages(&mut self) -> Result<Vec<usize>, Error> {
+        let pages = self.object(2)?;
+        let start = find(&pages, b"/Kids").ok_or(Error::InvalidPdf)?;
+        let end = start + find(&pages[start..], b"]").ok_or(Error::InvalidPdf)?;
+        Ok(references(&pages[start..end]))
+    }
+
+    /// The outline tree
+    pub fn outlines(&mut self) -> Result<Vec<Outline>, Error> {
+        let first = number_after(&self.object(3)?, b"/First");
+        let mut remaining = self.offsets.len();
+        self.outline_items(first, &mut remaining)
+    }
+
+    /// Entries of one level of the outline tree, from the first one
+    fn outline_items(
+        &mut self,
+        first: Option<usize>,
+        remaining: &mut usize,
+    ) -> Result<Vec<Outline>, Error> {
+        let mut outlines = Vec::new();
+        let mut next = first;
+        while let Some(id) = next {
+            // Loops in broken files
+            *remaining = remaining.checked_sub(1).ok_or(Error::InvalidPdf)?;
+
+            let item = self.object(id)?;
+            let title = find(&item, b"/Title")
+                .and_then(|start| literal_string(&item[start + 6..]))
+                .ok_or(Error::InvalidPdf)?;
+            let page = number_after(&item, b"/Dest [").ok_or(Error::InvalidPdf)?;
+            let children = self.outline_items(number_after(&item, b"/First"), remaining)?;
+            outlines.push(Outline {
+                page,
+                title: decode_text(&title),
+                children,
+            });
+
+            next = number_after(&item, b"/Next");
+        }
+        Ok(outlines)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .rposition(|window| window == needle)
+}
+
+fn parse_number(token: &[u8]) -> Option<usize> {
+    std::str::from_utf8(token).ok()?.parse().ok()
+}
+
+/// The first unsigned number in some bytes
+fn first_number(bytes: &[u8]) -> Option<usize> {
+    let start = bytes.iter().position(u8::is_ascii_digit)?;
+    let length = bytes[start..]
+        .iter()
+        .position(|byte| !byte.is_ascii_digit())
+        .unwrap_or(bytes.len() - start);
+    parse_number(&bytes[start..start + length])
+}
+
+/// The number following a key, e.g. the object number of `/Key 12 0 R`
+fn number_after(bytes: &[u8], key: &[u8]) -> Option<usize> {
+    let start = find(bytes, key)? + key.len();
+    let rest = &bytes[start..];
+    // Only whitespace between the key and its value
+    let value = rest.iter().position(|byte| !byte.is_ascii_whitespace())?;
+    if !rest[value].is_ascii_digit() {
+        return None;
+    }
+    first_number(rest)
+}
+
+/// Object numbers of all `N 0 R` references
+pub fn references(bytes: &[u8]) -> Vec<usize> {
+    let mut references = Vec::new();
+    renumber(bytes, |id| {
+        references.push(id);
+        id
+    });
+    references
+}
+
+/// Replace the object numbers of all `N 0 R` references
+pub fn renumber(bytes: &[u8], mut map: impl FnMut(usize) -> usize) -> Vec<u8> {
+    let mut renumbered = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        // Numbers start after a delimiter, not in the middle of a name
+        let starts = i == 0 || !(bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'.');
+        if !(starts && bytes[i].is_ascii_digit()) {
+            renumbered.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+
+        let length = bytes[i..]
+            .iter()
+            .position(|byte| !byte.is_ascii_digit())
+            .unwrap_or(bytes.len() - i);
+        let number = &bytes[i..i + length];
+        let rest = &bytes[i + length..];
+        let reference = rest.starts_with(b" 0 R")
+            && rest.get(4).is_none_or(|byte| !byte.is_ascii_alphanumeric());
+        match parse_number(number).filter(|_| reference) {
+            Some(id) => renumbered.extend(map(id).to_string().as_bytes()),
+            None => renumbered.extend(number),
+        }
+        i += length;
+    }
+    renumbered
+}
+
+/// Bytes of a literal string starting at the first `(`
+fn literal_string(bytes: &[u8]) -> Option<Vec<u8>> {
+    let start = bytes.iter().position(|byte| *byte == b'(')?;
+    let mut iter = bytes[start + 1..].iter().copied();
+    let mut string = Vec::new();
+    let mut depth = 1;
+
+    while let Some(byte) = iter.next() {
+        match byte {
+            b'\\' => match iter.next()? {
+                b'n' => string.push(b'\n'),
+                b'r' => string.push(b'\r'),
+                b't' => string.push(b'\t'),
+                b'b' => string.push(0x08),
+                b'f' => string.push(0x0C),
+                // Line continuation
+                b'\n' => {}
+                digit @ b'0'..=b'7' => {
+                    let mut value = (digit - b'0') as u32;
+                    let mut rest = iter.clone();
+                    for _ in 0..2 {
+                        match rest.next() {
+                            Some(digit @ b'0'..=b'7') => {
+                                value = value * 8 + (digit - b'0') as u32;
+                                iter.next();
+                            }
+                            _ => break,
+                        }
+                    }
+                    string.push(value as u8);
+                }
+                other => string.push(other),
+            },
+            b'(' => {
+                depth += 1;
+                string.push(byte);
+            }
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(string);
+                }
+                string.push(byte);
+            }
+            _ => string.push(byte),
+        }
+    }
+
+    None
+}
+
+/// A text string, UTF-16BE with a byte order mark or PDFDocEncoding
+fn decode_text(bytes: &[u8]) -> String {
+    match bytes.strip_prefix(&[0xFE, 0xFF]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        // Close enough to Latin-1 for titles
+        None => bytes.iter().map(|byte| *byte as char).collect(),
+    }
+}
@@ -0,0 +1,168 @@
+// Output split into several PDF files, for upload targets that cap the size
+// of a file. Each part is a complete PDF with the outlines of its pages.
+
+use std::io::Write;
+use std::path::Path;
+
+use crate::{Error, PageConfig, PageInfo, PDF};
+
+/// Bytes the page tree, outlines and xref table take per page when a part
+/// is finished, a generous estimate
+const FINISH_BYTES_PER_PAGE: u64 = 128;
+
+/// When to start a new part of a `SplitPDF`
+#[derive(Clone, Copy, PartialEq)]
+pub enum Split {
+    /// At most this many pages per part
+    Pages(usize),
+    /// Parts of at most about this many bytes. The size of the next page is
+    /// estimated from the last one, and a part always has at least one
+    /// page, so a single large page can go over the limit.
+    Bytes(u64),
+}
+
+/// A PDF written as several files, each created by a factory taking the
+/// index of the part (from 0) when the previous part is full.
+///
+/// When a part starts without an outline on its first page, the last
+/// outline of the previous part is repeated there, so each part shows the
+/// chapter it starts in. Every part uses the default page config. The
+/// crate writes no document metadata, so there is none to carry over.
+pub struct SplitPDF<W: Write, F: FnMut(usize) -> std::io::Result<W>> {
+    factory: F,
+    default_page_config: PageConfig,
+    split: Split,
+    // Pages left for the parts not created yet and the current one
+    remaining_pages: usize,
+    part: PDF<W>,
+    parts: usize,
+    // Size of the last page added, to estimate the next one
+    last_page_bytes: u64,
+    last_outline: Option<String>,
+    // Outline to repeat on the first page of the current part
+    carried_outline: Option<String>,
+}
+
+impl<W: Write, F: FnMut(usize) -> std::io::Result<W>> SplitPDF<W, F> {
+    pub fn create(
+        mut factory: F,
+        default_page_config: PageConfig,
+        total_pages: usize,
+        split: Split,
+    ) -> Result<Self, Error> {
+        if matches!(split, Split::Pages(0) | Split::Bytes(0)) {
+            return Err(Error::InvalidSplit);
+        }
+        let part = PDF::create(
+            factory(0)?,
+            default_page_config.clone(),
+            part_pages(split, total_pages),
+        )?;
+        Ok(SplitPDF {
+            factory,
+            default_page_config,
+            split,
+            remaining_pages: total_pages,
+            part,
+            parts: 1,
+            last_page_bytes: 0,
+            last_outline: None,
+            carried_outline: None,
+        })
+    }
+
+    /// Number of parts created so far, including the current one
+    pub fn parts(&self) -> usize {
+        self.parts
+    }
+
+    pub fn add_page_from_image(
+        &mut self,
+        image: image::DynamicImage,
+        outline: Option<String>,
+        page_config: Option<PageConfig>,
+    ) -> Result<PageInfo, Error> {
+        self.add_page(outline, |part, outline| {
+            part.add_page_from_image(image, outline, page_config)
+        })
+    }
+
+    pub fn add_page_from_path<P: AsRef<Path>>(
+        &mut self,
+        image_path: P,
+        outline: Option<String>,
+        page_config: Option<PageConfig>,
+    ) -> Result<PageInfo, Error> {
+        self.add_page(outline, |part, outline| {
+            part.add_page_from_path(image_path, outline, page_config)
+        })
+    }
+
+    fn add_page(
+        &mut self,
+        outline: Option<String>,
+        add: impl FnOnce(&mut PDF<W>, Option<String>) -> Result<PageInfo, Error>,
+    ) -> Result<PageInfo, Error> {
+        if self.is_full() {
+            self.next_part()?;
+        }
+
+        let explicit = outline.is_some();
+        let outline = outline.or_else(|| self.carried_outline.clone());
+        let start = self.part.position();
+        let info = add(&mut self.part, outline.clone())?;
+        if info.skipped {
+            return Ok(info);
+        }
+
+        self.last_page_bytes = self.part.position() - start;
+        self.remaining_pages -= 1;
+        self.carried_outline = None;
+        if explicit {
+            self.last_outline = outline;
+        }
+        Ok(info)
+    }
+
+    /// Whether the next page goes into a new part
+    fn is_full(&self) -> bool {
+        let pages = self.part.page_count();
+        // Too many pages fail in the current part
+        if pages == 0 || self.remaining_pages == 0 {
+            return false;
+        }
+        match self.split {
+            Split::Pages(max) => pages >= max,
+            Split::Bytes(max) => {
+                let finish = FINISH_BYTES_PER_PAGE * (pages as u64 + 1);
+                self.part.position() + self.last_page_bytes + finish > max
+            }
+        }
+    }
+
+    fn next_part(&mut self) -> std::io::Result<()> {
+        let part = PDF::create(
+            (self.factory)(self.parts)?,
+            self.default_page_config.clone(),
+            part_pages(self.split, self.remaining_pages),
+        )?;
+        std::mem::replace(&mut self.part, part).finish()?;
+        self.parts += 1;
+        self.carried_outline = self.last_outline.clone();
+        Ok(())
+    }
+
+    /// Finish the last part, returns the number of parts
+    pub fn finish(self) -> std::io::Result<usize> {
+        self.part.finish()?;
+        Ok(self.parts)
+    }
+}
+
+/// Pages a new part may hold
+fn part_pages(split: Split, remaining_pages: usize) -> usize {
+    match split {
+        Split::Pages(max) => remaining_pages.min(max),
+        Split::Bytes(_) => remaining_pages,
+    }
+}
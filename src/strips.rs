@@ -0,0 +1,101 @@
+// Compressed TIFF strips that PDF readers can decode themselves, copied
+// into the PDF without decoding them
+
+use std::io::{Cursor, Read, Seek};
+
+use tiff::decoder::Decoder;
+use tiff::tags::Tag;
+
+use crate::image::{encode_jpeg, EncodedImage};
+
+const GROUP_3: u16 = 3;
+const GROUP_4: u16 = 4;
+const JPEG: u16 = 7;
+/// Not among the tags the `tiff` crate names
+const T4_OPTIONS: Tag = Tag::Unknown(292);
+
+/// The first image of a TIFF file, if it is a single CCITT G3/G4 or JPEG
+/// strip
+pub fn read_tiff_strip(data: &[u8]) -> Option<EncodedImage> {
+    let mut decoder = Decoder::new(Cursor::new(data)).ok()?;
+    read_strip(&mut decoder, data)
+}
+
+/// The image of the current TIFF directory, if it is a single CCITT G3/G4
+/// or JPEG strip
+pub fn read_strip<R: Read + Seek>(decoder: &mut Decoder<R>, data: &[u8]) -> Option<EncodedImage> {
+    let tag =
+        |decoder: &mut Decoder<R>, tag: Tag| decoder.find_tag_unsigned::<u32>(tag).ok().flatten();
+
+    let compression = tag(decoder, Tag::Compression)? as u16;
+    if !matches!(compression, GROUP_3 | GROUP_4 | JPEG) {
+        return None;
+    }
+    // Bits filled from the least significant one
+    if tag(decoder, Tag::FillOrder) == Some(2) {
+        return None;
+    }
+
+    let offsets = decoder.get_tag_u64_vec(Tag::StripOffsets).ok()?;
+    let counts = decoder.get_tag_u64_vec(Tag::StripByteCounts).ok()?;
+    let (&[offset], &[count]) = (offsets.as_slice(), counts.as_slice()) else {
+        return None;
+    };
+    let strip = data.get(offset as usize..offset.checked_add(count)? as usize)?;
+
+    let (width, height) = decoder.dimensions().ok()?;
+    let photometric = tag(decoder, Tag::PhotometricInterpretation).unwrap_or(0);
+
+    match compression {
+        JPEG => {
+            let data = match decoder.get_tag_u8_vec(Tag::JPEGTables) {
+                // Shared tables go in front of the strip, between its SOI
+                // and the first segment
+                Ok(tables) if tables.len() > 4 && strip.len() > 2 => {
+                    let mut data = tables[..tables.len() - 2].to_vec();
+                    data.extend(&strip[2..]);
+                    data
+                }
+                _ => strip.to_vec(),
+            };
+            let info = crate::jpeg::read_info(&data)?;
+            if (info.width, info.height) != (width, height) {
+                return None;
+            }
+
+            let mut image = encode_jpeg(data, &info);
+            // Samples are RGB, not the YCbCr readers assume
+            if info.components == 3 && photometric == 2 && !info.adobe {
+                image.decode_parms = Some("<< /ColorTransform 0 >>".to_string());
+            }
+            Some(image)
+        }
+        _ => {
+            let options = tag(decoder, T4_OPTIONS).unwrap_or(0);
+            let k = match compression {
+                GROUP_4 => -1,
+                // Two-dimensional Group 3
+                _ if options & 1 != 0 => 1,
+                _ => 0,
+            };
+            let mut parms = format!("<< /K {} /Columns {} /Rows {}", k, width, height);
+            // Fill bits end every EOL on a byte boundary
+            if compression == GROUP_3 && options & 4 != 0 {
+                parms.push_str(" /EncodedByteAlign true");
+            }
+            parms.push_str(" >>");
+
+            Some(EncodedImage {
+                width,
+                height,
+                color_space: "/DeviceGray".to_string(),
+                bits_per_component: 1,
+                filter: Some("/CCITTFaxDecode".to_string()),
+                // Black is zero: the image is inverted
+                decode: (photometric == 1).then(|| "[1 0]".to_string()),
+                decode_parms: Some(parms),
+                data: strip.to_vec(),
+            })
+        }
+    }
+}
@@ -0,0 +1,49 @@
+// Output of a PDF, which only needs to move forward. Offsets of objects are
+// counted as bytes are written, so any `Write` will do, even stdout or a
+// socket.
+
+use std::io::{BufWriter, Write};
+
+/// A buffered writer counting the bytes written through it
+pub struct CountingWriter<W: Write> {
+    inner: BufWriter<W>,
+    position: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    /// Start counting from `position`, the number of bytes already in the
+    /// output
+    pub fn new(inner: W, position: u64) -> Self {
+        CountingWriter {
+            inner: BufWriter::new(inner),
+            position,
+        }
+    }
+
+    /// Number of bytes in the output so far
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// The output, with what is still buffered written to it
+    pub fn get_mut(&mut self) -> std::io::Result<&mut W> {
+        self.inner.flush()?;
+        Ok(self.inner.get_mut())
+    }
+
+    pub fn into_inner(self) -> std::io::Result<W> {
+        self.inner.into_inner().map_err(|err| err.into_error())
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
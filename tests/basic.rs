@@ -55,6 +55,126 @@ fn with_margin() {
 }
 
 #[test]
+fn with_mirrored_margin() {
+    let config = scannedpdf::PageConfig::new()
+        .margin(scannedpdf::Margin::mirrored(40, 40, 80, 20))
+        .horizontal_alignment(scannedpdf::Alignment::Start);
+
+    // Delete the file if it exists
+    let _ = std::fs::remove_file("./assets/mirrored.pdf");
+
+    let images = get_images();
+    assert!(images.len() >= 2);
+
+    let mut file = scannedpdf::create("./assets/mirrored.pdf", config, images.len()).unwrap();
+
+    for path in &images {
+        println!("Adding page from {:?}", path);
+        file.add_page_from_path(path, None, None).unwrap();
+        println!("Page added");
+    }
+
+    println!("Finishing");
+    file.finish().unwrap();
+    println!("Finished");
+
+    // x offset of the image on each page, from its `cm` operator
+    let pdf = std::fs::read("./assets/mirrored.pdf").unwrap();
+    let offsets: Vec<u32> = pdf
+        .split(|byte| *byte == b'\n')
+        .filter(|line| line.ends_with(b" cm"))
+        .map(|line| {
+            let line = std::str::from_utf8(line).unwrap();
+            line.split(' ').nth(4).unwrap().parse().unwrap()
+        })
+        .collect();
+    assert_eq!(offsets.len(), images.len());
+    for (page, x) in offsets.into_iter().enumerate() {
+        // The inner margin is on the left of odd pages, counted from 1
+        let expected = if page % 2 == 0 { 80 } else { 20 };
+        assert_eq!(x, expected, "page {}", page + 1);
+    }
+}
+
+#[test]
+fn with_mrc() {
+    let config = scannedpdf::PageConfig::new().encoding(scannedpdf::Encoding::Mrc);
+
+    // Delete the file if it exists
+    let _ = std::fs::remove_file("./assets/mrc.pdf");
+
+    let images = get_images();
+
+    let mut file = scannedpdf::create("./assets/mrc.pdf", config, images.len()).unwrap();
+
+    for path in images {
+        println!("Adding page from {:?}", path);
+        file.add_page_from_path(path, None, None).unwrap();
+        println!("Page added");
+    }
+
+    println!("Finishing");
+    file.finish().unwrap();
+    println!("Finished");
+
+    let pdf = std::fs::read("./assets/mrc.pdf").unwrap();
+    assert!(pdf.windows(6).any(|window| window == b"/SMask"));
+
+    // Dark text on a coloured page, at a size the layer scales divide
+    let image = image::RgbImage::from_fn(300, 180, |x, y| {
+        if (40..50).contains(&(y % 60)) && x % 20 < 14 {
+            image::Rgb([20, 20, 120])
+        } else {
+            image::Rgb([230, 210, 170])
+        }
+    });
+    let config = scannedpdf::PageConfig::new()
+        .encoding(scannedpdf::Encoding::Mrc)
+        .size(scannedpdf::PageSize::Image);
+    let mut buf = Vec::new();
+    let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 1).unwrap();
+    file.add_page_from_image(image::DynamicImage::ImageRgb8(image), None, None)
+        .unwrap();
+    file.finish().unwrap();
+
+    // Dictionaries of the objects by number, streams left out
+    let text = String::from_utf8_lossy(&buf);
+    let objects: std::collections::HashMap<usize, &str> = text
+        .split("endobj\n")
+        .filter_map(|object| {
+            let (header, body) = object.split_once(" 0 obj\n")?;
+            let id = header.rsplit('\n').next()?.parse().ok()?;
+            Some((id, body.split("stream\n").next()?))
+        })
+        .collect();
+    let reference = |dict: &str, key: &str| -> usize {
+        let value = dict.split(key).nth(1).unwrap().trim_start();
+        value.split_whitespace().next().unwrap().parse().unwrap()
+    };
+    let size = |id: usize| {
+        (
+            reference(objects[&id], "/Width"),
+            reference(objects[&id], "/Height"),
+        )
+    };
+
+    let page = objects
+        .values()
+        .find(|dict| dict.contains("/Type /Page\n"))
+        .unwrap();
+    let background = reference(page, "/Bg");
+    let foreground = reference(page, "/Fg");
+    // Background at 1/3, foreground at 1/6 and the mask at full resolution
+    assert_eq!(size(background), (100, 60));
+    assert!(!objects[&background].contains("/SMask"));
+    assert_eq!(size(foreground), (50, 30));
+    let mask = reference(objects[&foreground], "/SMask");
+    assert_eq!(size(mask), (300, 180));
+    assert!(objects[&mask].contains("/BitsPerComponent 1"));
+}
+
+#[test]
+#[allow(clippy::explicit_counter_loop)]
 fn with_outlines() {
     let config = scannedpdf::PageConfig::new();
 
@@ -78,3 +198,1361 @@ fn with_outlines() {
     file.finish().unwrap();
     println!("Finished");
 }
+
+#[test]
+fn invalid_margin() {
+    let config = scannedpdf::PageConfig::new().margin(scannedpdf::Margin::new(600, 100));
+
+    let images = get_images();
+
+    let mut buf = Vec::new();
+    let mut file =
+        scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, images.len()).unwrap();
+
+    let result = file.add_page_from_path(&images[0], None, None);
+    assert!(matches!(result, Err(scannedpdf::Error::InvalidGeometry)));
+
+    // The page would grow past the largest size
+    let config = scannedpdf::PageConfig::new()
+        .size(scannedpdf::PageSize::Image)
+        .margin(scannedpdf::Margin::new(u32::MAX, 0));
+    let result = file.add_page_from_path(&images[0], None, Some(config));
+    assert!(matches!(result, Err(scannedpdf::Error::InvalidGeometry)));
+
+    // Sides adding up past the largest margin
+    for margin in [
+        scannedpdf::Margin::sides(0, u32::MAX, 0, 10),
+        scannedpdf::Margin::mirrored(u32::MAX, 10, 0, 0),
+    ] {
+        let config = scannedpdf::PageConfig::new().margin(margin);
+        let result = file.add_page_from_path(&images[0], None, Some(config));
+        assert!(matches!(result, Err(scannedpdf::Error::InvalidGeometry)));
+    }
+}
+
+#[test]
+fn with_rotation() {
+    let config = scannedpdf::PageConfig::new().rotation(scannedpdf::Rotation::Clockwise90);
+
+    let images = get_images();
+
+    let mut buf = Vec::new();
+    let mut file =
+        scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, images.len()).unwrap();
+
+    for path in images {
+        file.add_page_from_path(path, None, None).unwrap();
+    }
+    file.finish().unwrap();
+
+    assert!(buf.windows(10).any(|window| window == b"/Rotate 90"));
+}
+
+/// Copy a JPEG with an EXIF orientation tag inserted after SOI
+fn with_orientation(source: &PathBuf, target: &str, orientation: u8) {
+    let jpeg = std::fs::read(source).unwrap();
+
+    let mut tiff = b"Exif\0\0MM\0\x2a\0\0\0\x08".to_vec();
+    // One IFD entry: Orientation, SHORT, count 1
+    tiff.extend([0, 1, 0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, orientation, 0, 0]);
+    // No next IFD
+    tiff.extend([0, 0, 0, 0]);
+
+    let mut data = jpeg[..2].to_vec();
+    data.extend([0xFF, 0xE1]);
+    data.extend(((tiff.len() + 2) as u16).to_be_bytes());
+    data.extend(tiff);
+    data.extend(&jpeg[2..]);
+
+    std::fs::write(target, data).unwrap();
+}
+
+#[cfg(feature = "exif")]
+#[test]
+fn exif_orientation() {
+    let image = get_images()
+        .into_iter()
+        .find(|path| path.ends_with("3.jpg"))
+        .unwrap();
+    // Keep it out of `get_images`
+    let _ = std::fs::create_dir("./assets/exif");
+    with_orientation(&image, "./assets/exif/rotated.jpg", 6);
+
+    for (auto_rotate, media_box) in [(true, "[0 0 600 400]"), (false, "[0 0 400 600]")] {
+        let config = scannedpdf::PageConfig::new()
+            .size(scannedpdf::PageSize::Image)
+            .auto_rotate(auto_rotate);
+
+        let mut buf = Vec::new();
+        let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 1).unwrap();
+        file.add_page_from_path("./assets/exif/rotated.jpg", None, None)
+            .unwrap();
+        file.finish().unwrap();
+
+        let media_box = format!("/MediaBox {}", media_box);
+        assert!(buf
+            .windows(media_box.len())
+            .any(|window| window == media_box.as_bytes()));
+    }
+}
+
+#[test]
+fn deskew() {
+    // Text-like lines going down to the right by 2 degrees
+    let slope = 2f32.to_radians().tan();
+    let image = image::RgbImage::from_fn(1200, 900, |x, y| {
+        let y = y as f32 - x as f32 * slope;
+        if x > 100 && x < 1100 && y > 50.0 && y < 800.0 && y as u32 % 40 < 8 {
+            image::Rgb([0, 0, 0])
+        } else {
+            image::Rgb([255, 255, 255])
+        }
+    });
+
+    let config = scannedpdf::PageConfig::new().deskew(true);
+
+    let mut buf = Vec::new();
+    let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 1).unwrap();
+    let info = file
+        .add_page_from_image(image::DynamicImage::ImageRgb8(image), None, None)
+        .unwrap();
+    file.finish().unwrap();
+
+    let skew = info.skew.unwrap();
+    assert!((skew - 2.0).abs() < 0.2, "detected skew {}", skew);
+}
+
+#[test]
+fn auto_crop() {
+    // Scanner bed around a white page with some content on it
+    let image = image::RgbImage::from_fn(600, 400, |x, y| {
+        if x < 20 || y < 20 || x >= 580 || y >= 380 {
+            image::Rgb([10, 10, 10])
+        } else if (100..300).contains(&x) && (50..250).contains(&y) {
+            image::Rgb([120, 60, 60])
+        } else {
+            image::Rgb([250, 250, 245])
+        }
+    });
+
+    let config = scannedpdf::PageConfig::new()
+        .size(scannedpdf::PageSize::Image)
+        .crop(scannedpdf::Crop::Auto(32));
+
+    let mut buf = Vec::new();
+    let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 1).unwrap();
+    file.add_page_from_image(image::DynamicImage::ImageRgb8(image), None, None)
+        .unwrap();
+    file.finish().unwrap();
+
+    let media_box = b"/MediaBox [0 0 200 200]";
+    assert!(buf
+        .windows(media_box.len())
+        .any(|window| window == media_box));
+}
+
+#[test]
+fn with_filters() {
+    let config = scannedpdf::PageConfig::new()
+        .filter(scannedpdf::Filter::Normalize)
+        .filter(scannedpdf::Filter::Whiten(220))
+        .filter(scannedpdf::Filter::Despeckle)
+        .filter(scannedpdf::Filter::Sauvola(25, 0.3));
+
+    // Delete the file if it exists
+    let _ = std::fs::remove_file("./assets/filters.pdf");
+
+    let images = get_images();
+
+    let mut file = scannedpdf::create("./assets/filters.pdf", config, images.len()).unwrap();
+
+    for (i, path) in images.into_iter().enumerate() {
+        // Alternate between both binarisation methods
+        let config = if i % 2 == 0 {
+            None
+        } else {
+            Some(scannedpdf::PageConfig::new().filter(scannedpdf::Filter::Otsu))
+        };
+        println!("Adding page from {:?}", path);
+        file.add_page_from_path(path, None, config).unwrap();
+        println!("Page added");
+    }
+
+    println!("Finishing");
+    file.finish().unwrap();
+    println!("Finished");
+
+    let pdf = std::fs::read("./assets/filters.pdf").unwrap();
+    assert!(pdf.windows(11).any(|window| window == b"/DeviceGray"));
+}
+
+#[test]
+fn skip_blank_pages() {
+    // A blank backside with some specks and a dark scanner edge
+    let blank = image::RgbImage::from_fn(1240, 1754, |x, y| {
+        if x < 20 || (x * 7 + y * 13) % 997 == 0 {
+            image::Rgb([0, 0, 0])
+        } else {
+            image::Rgb([245, 245, 240])
+        }
+    });
+    let blank = image::DynamicImage::ImageRgb8(blank);
+
+    let config = scannedpdf::PageConfig::new().blank_page(scannedpdf::BlankPage::Skip(0.005));
+
+    let images = get_images();
+
+    let mut buf = Vec::new();
+    let mut file =
+        scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, images.len() * 2).unwrap();
+
+    for path in &images {
+        let info = file
+            .add_page_from_path(path, Some("Page".to_string()), None)
+            .unwrap();
+        assert!(!info.skipped);
+
+        let info = file
+            .add_page_from_image(blank.clone(), Some("Blank".to_string()), None)
+            .unwrap();
+        assert!(info.skipped);
+    }
+    file.finish().unwrap();
+
+    let count = format!("/Type /Pages\n/Count {}\n", images.len());
+    assert!(buf
+        .windows(count.len())
+        .any(|window| window == count.as_bytes()));
+}
+
+#[test]
+fn with_alpha() {
+    // A red disc on a transparent background
+    let image = image::RgbaImage::from_fn(300, 300, |x, y| {
+        let (dx, dy) = (x as i32 - 150, y as i32 - 150);
+        if dx * dx + dy * dy < 100 * 100 {
+            image::Rgba([220, 30, 30, 255])
+        } else {
+            image::Rgba([0, 0, 0, 0])
+        }
+    });
+    let image = image::DynamicImage::ImageRgba8(image);
+
+    for (alpha, has_smask) in [
+        (scannedpdf::Alpha::SoftMask, true),
+        (scannedpdf::Alpha::Flatten(255, 255, 255), false),
+    ] {
+        let config = scannedpdf::PageConfig::new().alpha(alpha);
+
+        let mut buf = Vec::new();
+        let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 1).unwrap();
+        file.add_page_from_image(image.clone(), None, None).unwrap();
+        file.finish().unwrap();
+
+        assert_eq!(buf.windows(6).any(|window| window == b"/SMask"), has_smask);
+    }
+
+    // Opaque pixels need no mask, as in GIF and WebP frames
+    let image = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+        300,
+        300,
+        image::Rgba([220, 30, 30, 255]),
+    ));
+    let mut buf = Vec::new();
+    let mut file = scannedpdf::PDF::create(
+        std::io::Cursor::new(&mut buf),
+        scannedpdf::PageConfig::new(),
+        1,
+    )
+    .unwrap();
+    file.add_page_from_image(image, None, None).unwrap();
+    file.finish().unwrap();
+    assert!(!buf.windows(6).any(|window| window == b"/SMask"));
+}
+
+#[test]
+fn cmyk_passthrough() {
+    let (width, height) = (200u16, 100u16);
+    let mut cmyk = Vec::new();
+    for _ in 0..height {
+        for x in 0..width {
+            cmyk.extend([x as u8, 255 - x as u8, 0, 0]);
+        }
+    }
+
+    // Only the header of the profile matters here
+    let mut profile = vec![0u8; 128];
+    profile[16..20].copy_from_slice(b"CMYK");
+
+    let mut jpeg = Vec::new();
+    let mut encoder = jpeg_encoder::Encoder::new(&mut jpeg, 90);
+    encoder.add_icc_profile(&profile).unwrap();
+    encoder
+        .encode(&cmyk, width, height, jpeg_encoder::ColorType::Cmyk)
+        .unwrap();
+
+    // Keep it out of `get_images`
+    let _ = std::fs::create_dir("./assets/cmyk");
+    std::fs::write("./assets/cmyk/cmyk.jpg", &jpeg).unwrap();
+
+    let config = scannedpdf::PageConfig::new().passthrough(true);
+
+    let mut buf = Vec::new();
+    let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 1).unwrap();
+    file.add_page_from_path("./assets/cmyk/cmyk.jpg", None, None)
+        .unwrap();
+    file.finish().unwrap();
+
+    let contains = |needle: &[u8]| buf.windows(needle.len()).any(|window| window == needle);
+    // The JPEG is embedded untouched
+    assert!(contains(&jpeg));
+    assert!(contains(b"/Decode [1 0 1 0 1 0 1 0]"));
+    assert!(contains(b"/ICCBased"));
+    assert!(contains(b"/Alternate /DeviceCMYK"));
+}
+
+#[test]
+fn lossless_16bit() {
+    // A gradient that needs more than 256 levels
+    let image = image::ImageBuffer::from_fn(1024, 64, |x, _| image::Luma([(x * 64) as u16]));
+    let image = image::DynamicImage::ImageLuma16(image);
+
+    for (encoding, bits) in [
+        (scannedpdf::Encoding::Jpeg, &b"/BitsPerComponent 8"[..]),
+        (scannedpdf::Encoding::Lossless, &b"/BitsPerComponent 16"[..]),
+    ] {
+        let config = scannedpdf::PageConfig::new()
+            .size(scannedpdf::PageSize::Image)
+            .encoding(encoding);
+
+        let mut buf = Vec::new();
+        let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 1).unwrap();
+        file.add_page_from_image(image.clone(), None, None).unwrap();
+        file.finish().unwrap();
+
+        assert!(buf.windows(bits.len()).any(|window| window == bits));
+    }
+}
+
+#[cfg(not(feature = "jpeg2000"))]
+#[test]
+fn jpeg2000_disabled() {
+    let config = scannedpdf::PageConfig::new().encoding(scannedpdf::Encoding::Jpeg2000);
+
+    let mut buf = Vec::new();
+    let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 1).unwrap();
+    let image = image::DynamicImage::ImageRgb8(image::RgbImage::new(40, 30));
+    assert!(matches!(
+        file.add_page_from_image(image, None, None),
+        Err(scannedpdf::Error::Image(image::ImageError::Unsupported(_)))
+    ));
+}
+
+#[cfg(feature = "jpeg2000")]
+#[test]
+fn jpeg2000() {
+    let config = scannedpdf::PageConfig::new().encoding(scannedpdf::Encoding::Jpeg2000);
+
+    let mut buf = Vec::new();
+    let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 1).unwrap();
+    file.add_page_from_path(&get_images()[0], None, None)
+        .unwrap();
+    file.finish().unwrap();
+
+    assert!(buf.windows(10).any(|window| window == b"/JPXDecode"));
+    // A raw codestream starts with SOC and SIZ markers
+    assert!(buf
+        .windows(4)
+        .any(|window| window == [0xFF, 0x4F, 0xFF, 0x51]));
+}
+
+#[test]
+fn jpeg_options() {
+    let image = get_images().remove(0);
+    let encode = |config: scannedpdf::PageConfig| {
+        let mut buf = Vec::new();
+        let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 1).unwrap();
+        file.add_page_from_path(&image, None, None).unwrap();
+        file.finish().unwrap();
+        buf
+    };
+
+    let baseline = encode(scannedpdf::PageConfig::new());
+    let small = encode(
+        scannedpdf::PageConfig::new()
+            .subsampling(scannedpdf::Subsampling::Chroma420)
+            .progressive(true)
+            .optimize(true),
+    );
+
+    assert!(small.len() < baseline.len());
+}
+
+/// Length of the first image stream of a PDF
+fn image_length(pdf: &[u8]) -> usize {
+    let image = pdf
+        .windows(15)
+        .position(|window| window == b"/Subtype /Image")
+        .unwrap();
+    let start = image
+        + pdf[image..]
+            .windows(8)
+            .position(|window| window == b"/Length ")
+            .unwrap()
+        + 8;
+    let end = start + pdf[start..].iter().position(|byte| *byte == b'\n').unwrap();
+    std::str::from_utf8(&pdf[start..end])
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn quality_target() {
+    let image = get_images().remove(0);
+
+    let budget = 40_000;
+    for target in [
+        scannedpdf::Target::Size(budget),
+        scannedpdf::Target::Ssim(0.9),
+    ] {
+        let config = scannedpdf::PageConfig::new().target(target);
+
+        let mut buf = Vec::new();
+        let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 1).unwrap();
+        let info = file.add_page_from_path(&image, None, None).unwrap();
+        file.finish().unwrap();
+
+        let quality = info.quality.unwrap();
+        assert!((1..=100).contains(&quality));
+        if let scannedpdf::Target::Size(_) = target {
+            // The image stream itself fits
+            assert!(image_length(&buf) <= budget);
+        }
+    }
+
+    // The budget counts the stream as written, not the JPEG data before
+    // it is compressed again
+    let mut seed = 1u32;
+    let noise = image::RgbImage::from_fn(64, 64, |_, _| {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        let [r, g, b, _] = seed.to_be_bytes();
+        image::Rgb([r, g, b])
+    });
+    let write = |config: scannedpdf::PageConfig| {
+        let mut buf = Vec::new();
+        let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 1).unwrap();
+        let info = file
+            .add_page_from_image(image::DynamicImage::ImageRgb8(noise.clone()), None, None)
+            .unwrap();
+        file.finish().unwrap();
+        (image_length(&buf), info.quality)
+    };
+    let (budget, _) = write(scannedpdf::PageConfig::new().quality(60));
+    let (length, quality) =
+        write(scannedpdf::PageConfig::new().target(scannedpdf::Target::Size(budget)));
+    assert_eq!(quality, Some(60));
+    assert_eq!(length, budget);
+}
+
+#[test]
+fn multi_frame() {
+    let _ = std::fs::create_dir("./assets/frames");
+
+    // A TIFF with three pages of different shades
+    let mut tiff = std::fs::File::create("./assets/frames/pages.tiff").unwrap();
+    let mut encoder = tiff::encoder::TiffEncoder::new(&mut tiff).unwrap();
+    for shade in [0u8, 100, 200] {
+        encoder
+            .write_image::<tiff::encoder::colortype::Gray8>(300, 400, &vec![shade; 300 * 400])
+            .unwrap();
+    }
+    drop(tiff);
+
+    // A GIF with two frames
+    let mut gif = std::fs::File::create("./assets/frames/frames.gif").unwrap();
+    let mut encoder = image::codecs::gif::GifEncoder::new(&mut gif);
+    encoder
+        .encode_frames((0..2).map(|i| {
+            image::Frame::new(image::RgbaImage::from_pixel(
+                200,
+                200,
+                image::Rgba([i * 200, 0, 0, 255]),
+            ))
+        }))
+        .unwrap();
+    drop(encoder);
+
+    let mut buf = Vec::new();
+    let mut file = scannedpdf::PDF::create(
+        std::io::Cursor::new(&mut buf),
+        scannedpdf::PageConfig::new(),
+        6,
+    )
+    .unwrap();
+    let pages = file
+        .add_pages_from_path(
+            "./assets/frames/pages.tiff",
+            Some("Pages".to_string()),
+            None,
+        )
+        .unwrap();
+    assert_eq!(pages.len(), 3);
+    let frames = file
+        .add_pages_from_path("./assets/frames/frames.gif", None, None)
+        .unwrap();
+    assert_eq!(frames.len(), 2);
+
+    // Only one page left: the second frame overflows
+    let err = file
+        .add_pages_from_path("./assets/frames/frames.gif", None, None)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        scannedpdf::Error::Frame(1, ref err) if matches!(**err, scannedpdf::Error::PageOverflow)
+    ));
+    file.finish().unwrap();
+
+    assert!(buf.windows(8).any(|window| window == b"/Count 6"));
+}
+
+/// Width, height, bits per sample, compression, photometric interpretation
+/// and the only strip of a TIFF page
+type TiffPage = (u32, u32, u16, u16, u16, Vec<u8>);
+
+/// A little-endian TIFF with one page per entry of `pages`
+fn tiff_file(pages: &[TiffPage]) -> Vec<u8> {
+    let mut data = b"II*\0".to_vec();
+    let mut next_ifd = data.len();
+    data.extend(0u32.to_le_bytes());
+
+    for (width, height, bits, compression, photometric, strip) in pages {
+        let strip_offset = data.len() as u32;
+        data.extend(strip);
+        if data.len() % 2 == 1 {
+            data.push(0);
+        }
+
+        let ifd = data.len() as u32;
+        data[next_ifd..next_ifd + 4].copy_from_slice(&ifd.to_le_bytes());
+
+        // Tag, type (3 = SHORT, 4 = LONG) and value
+        let entries: [(u16, u16, u32); 8] = [
+            (256, 4, *width),
+            (257, 4, *height),
+            (258, 3, *bits as u32),
+            (259, 3, *compression as u32),
+            (262, 3, *photometric as u32),
+            (273, 4, strip_offset),
+            (278, 4, *height),
+            (279, 4, strip.len() as u32),
+        ];
+        data.extend((entries.len() as u16).to_le_bytes());
+        for (tag, kind, value) in entries {
+            data.extend(tag.to_le_bytes());
+            data.extend(kind.to_le_bytes());
+            data.extend(1u32.to_le_bytes());
+            if kind == 3 {
+                data.extend((value as u16).to_le_bytes());
+                data.extend([0, 0]);
+            } else {
+                data.extend(value.to_le_bytes());
+            }
+        }
+        next_ifd = data.len();
+        data.extend(0u32.to_le_bytes());
+    }
+
+    data
+}
+
+#[test]
+fn tiff_passthrough() {
+    let _ = std::fs::create_dir("./assets/tiff");
+
+    // An all white Group 4 page: one V0 code per row, then EOFB
+    let g4 = vec![0xFF, 0x00, 0x10, 0x01];
+    // A grey JPEG strip
+    let mut jpeg = Vec::new();
+    jpeg_encoder::Encoder::new(&mut jpeg, 90)
+        .encode(&vec![128; 64 * 32], 64, 32, jpeg_encoder::ColorType::Luma)
+        .unwrap();
+
+    let data = tiff_file(&[
+        (100, 8, 1, 4, 0, g4.clone()),
+        (64, 32, 8, 7, 1, jpeg.clone()),
+    ]);
+    std::fs::write("./assets/tiff/strips.tiff", data).unwrap();
+    let data = tiff_file(&[(100, 8, 1, 4, 1, g4.clone())]);
+    std::fs::write("./assets/tiff/g4.tiff", data).unwrap();
+
+    let config = scannedpdf::PageConfig::new().passthrough(true);
+    let mut buf = Vec::new();
+    let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 3).unwrap();
+    let pages = file
+        .add_pages_from_path("./assets/tiff/strips.tiff", None, None)
+        .unwrap();
+    assert_eq!(pages.len(), 2);
+    file.add_page_from_path("./assets/tiff/g4.tiff", None, None)
+        .unwrap();
+    file.finish().unwrap();
+
+    let contains = |needle: &[u8]| buf.windows(needle.len()).any(|window| window == needle);
+    assert!(contains(b"/Filter /CCITTFaxDecode"));
+    assert!(contains(b"/DecodeParms << /K -1 /Columns 100 /Rows 8 >>"));
+    // Black is zero in the second file
+    assert!(contains(b"/Decode [1 0]"));
+    assert!(contains(&jpeg));
+}
+
+#[test]
+fn png_passthrough() {
+    let _ = std::fs::create_dir("./assets/png");
+
+    let rgb = image::RgbImage::from_fn(120, 80, |x, y| image::Rgb([x as u8, y as u8, 90]));
+    rgb.save("./assets/png/rgb.png").unwrap();
+
+    // A 2 bit palette image
+    let mut palette = Vec::new();
+    let mut encoder = png::Encoder::new(&mut palette, 16, 4);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Two);
+    encoder.set_palette(vec![255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255]);
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&[0b00_01_10_11; 16]).unwrap();
+    writer.finish().unwrap();
+    std::fs::write("./assets/png/palette.png", palette).unwrap();
+
+    let config = scannedpdf::PageConfig::new().passthrough(true);
+    let mut buf = Vec::new();
+    let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 2).unwrap();
+    file.add_page_from_path("./assets/png/rgb.png", None, None)
+        .unwrap();
+    file.add_page_from_path("./assets/png/palette.png", None, None)
+        .unwrap();
+    file.finish().unwrap();
+
+    let contains = |needle: &[u8]| buf.windows(needle.len()).any(|window| window == needle);
+    assert!(contains(
+        b"/DecodeParms << /Predictor 15 /Colors 3 /BitsPerComponent 8 /Columns 120 >>"
+    ));
+    assert!(contains(
+        b"/ColorSpace [/Indexed /DeviceRGB 3 <FF000000FF000000FFFFFFFF>]"
+    ));
+    assert!(contains(b"/BitsPerComponent 2"));
+}
+
+#[test]
+fn indexed() {
+    // Four flat colours
+    let image = image::RgbImage::from_fn(100, 60, |x, y| match (x < 50, y < 30) {
+        (true, true) => image::Rgb([255, 0, 0]),
+        (true, false) => image::Rgb([0, 255, 0]),
+        (false, true) => image::Rgb([0, 0, 255]),
+        (false, false) => image::Rgb([255, 255, 255]),
+    });
+
+    for (image, colors, bits) in [
+        (
+            image::DynamicImage::ImageRgb8(image),
+            16,
+            &b"/BitsPerComponent 2"[..],
+        ),
+        // Quantised down to 16 colours
+        (
+            image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+                image::Rgb([(x * 4) as u8, (y * 4) as u8, 128])
+            })),
+            16,
+            &b"/BitsPerComponent 4"[..],
+        ),
+    ] {
+        let config = scannedpdf::PageConfig::new().encoding(scannedpdf::Encoding::Indexed(colors));
+
+        let mut buf = Vec::new();
+        let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 1).unwrap();
+        file.add_page_from_image(image, None, None).unwrap();
+        file.finish().unwrap();
+
+        let contains = |needle: &[u8]| buf.windows(needle.len()).any(|window| window == needle);
+        assert!(contains(b"/ColorSpace [/Indexed /DeviceRGB "));
+        assert!(contains(bits));
+    }
+}
+
+#[test]
+fn append() {
+    let page = || image::DynamicImage::ImageRgb8(image::RgbImage::new(40, 30));
+
+    let mut buf = Vec::new();
+    let config = scannedpdf::PageConfig::new();
+    let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 2).unwrap();
+    file.add_page_from_image(page(), Some("Chapter (1)".to_string()), None)
+        .unwrap();
+    file.add_page_from_image(page(), None, None).unwrap();
+    file.finish().unwrap();
+    let original = buf.clone();
+
+    let config = scannedpdf::PageConfig::new();
+    let mut file = scannedpdf::PDF::append(std::io::Cursor::new(&mut buf), config, 1).unwrap();
+    file.add_page_from_image(page(), Some("Chapter \\2".to_string()), None)
+        .unwrap();
+    assert!(matches!(
+        file.add_page_from_image(page(), None, None),
+        Err(scannedpdf::Error::PageOverflow)
+    ));
+    file.finish().unwrap();
+
+    let contains = |needle: &[u8]| buf.windows(needle.len()).any(|window| window == needle);
+    assert!(buf.starts_with(&original));
+    assert!(contains(b"/Count 3"));
+    assert!(contains(b"/Prev "));
+
+    // Appending again follows the chain of updates
+    let config = scannedpdf::PageConfig::new();
+    let mut file = scannedpdf::PDF::append(std::io::Cursor::new(&mut buf), config, 1).unwrap();
+    file.add_page_from_image(page(), Some("Chapter 3".to_string()), None)
+        .unwrap();
+    file.finish().unwrap();
+    std::fs::create_dir_all("./assets/append").unwrap();
+    std::fs::write("./assets/append/append.pdf", &buf).unwrap();
+
+    let contains = |needle: &[u8]| buf.windows(needle.len()).any(|window| window == needle);
+    assert!(contains(b"/Count 4"));
+
+    // Not a PDF of this crate, and xref subsections overflowing or longer
+    // than the file
+    for header in [
+        "",
+        "xref\n18446744073709551615 2\n",
+        "xref\n0 99999999999\n",
+    ] {
+        let data = format!(
+            "%PDF-1.7\n{}trailer\n<<\n/Size 1\n/Root 1 0 R\n>>\nstartxref\n9\n%%EOF\n",
+            header
+        );
+        assert!(matches!(
+            scannedpdf::PDF::append(
+                std::io::Cursor::new(data.into_bytes()),
+                scannedpdf::PageConfig::new(),
+                1
+            ),
+            Err(scannedpdf::Error::InvalidPdf)
+        ));
+    }
+}
+
+#[test]
+fn merge() {
+    std::fs::create_dir_all("./assets/merge").unwrap();
+    let page = |shade: u8| {
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(40, 30, image::Rgb([shade; 3])))
+    };
+
+    for (name, pages) in [("chapter1", 2), ("chapter2", 3)] {
+        let config = scannedpdf::PageConfig::new().encoding(scannedpdf::Encoding::Lossless);
+        let mut file =
+            scannedpdf::create(format!("./assets/merge/{}.pdf", name), config, pages).unwrap();
+        for i in 0..pages {
+            file.add_page_from_image(page(i as u8 * 50), Some(format!("Page {}", i + 1)), None)
+                .unwrap();
+        }
+        file.finish().unwrap();
+    }
+
+    scannedpdf::merge(
+        "./assets/merge/volume.pdf",
+        &["./assets/merge/chapter1.pdf", "./assets/merge/chapter2.pdf"],
+    )
+    .unwrap();
+    let volume = std::fs::read("./assets/merge/volume.pdf").unwrap();
+    let contains = |needle: &[u8]| volume.windows(needle.len()).any(|window| window == needle);
+    assert!(contains(b"/Count 5\n/Kids"));
+    // Two chapters with their pages
+    assert!(contains(b"/Count 7\n"));
+    assert!(contains(b"/Count 3\n"));
+
+    // Merged files nest one level deeper
+    let mut buf = Vec::new();
+    let mut file = scannedpdf::PDF::create(
+        std::io::Cursor::new(&mut buf),
+        scannedpdf::PageConfig::new(),
+        6,
+    )
+    .unwrap();
+    let pages = file
+        .add_pages_from_pdf(
+            std::fs::File::open("./assets/merge/volume.pdf").unwrap(),
+            None,
+        )
+        .unwrap();
+    assert_eq!(pages, 5);
+    file.add_page_from_image(page(0), Some("Cover".to_string()), None)
+        .unwrap();
+    assert!(matches!(
+        file.add_pages_from_pdf(
+            std::fs::File::open("./assets/merge/chapter1.pdf").unwrap(),
+            None
+        ),
+        Err(scannedpdf::Error::PageOverflow)
+    ));
+    file.finish().unwrap();
+    std::fs::write("./assets/merge/nested.pdf", &buf).unwrap();
+
+    let contains = |needle: &[u8]| buf.windows(needle.len()).any(|window| window == needle);
+    assert!(contains(b"/Count 6\n/Kids"));
+    assert!(contains(b"/Count 8\n"));
+}
+
+#[test]
+fn merge_broken_input() {
+    std::fs::create_dir_all("./assets/merge").unwrap();
+    let page = || image::DynamicImage::ImageRgb8(image::RgbImage::new(40, 30));
+    let mut input = Vec::new();
+    let mut file = scannedpdf::PDF::create(
+        std::io::Cursor::new(&mut input),
+        scannedpdf::PageConfig::new(),
+        4,
+    )
+    .unwrap();
+    for i in 0..4 {
+        file.add_page_from_image(page(), Some(format!("Page {}", i + 1)), None)
+            .unwrap();
+    }
+    file.finish().unwrap();
+
+    // Files in the xref format of older versions merge like any other
+    std::fs::write("./assets/merge/old.pdf", old_xref_format(&input)).unwrap();
+    scannedpdf::merge(
+        "./assets/merge/old_volume.pdf",
+        &["./assets/merge/old.pdf", "./assets/merge/old.pdf"],
+    )
+    .unwrap();
+    let volume = std::fs::read("./assets/merge/old_volume.pdf").unwrap();
+    assert!(volume
+        .windows(14)
+        .any(|window| window == b"/Count 8\n/Kids"));
+
+    // The last page's contents refer to an object that does not exist
+    let start = input
+        .windows(10)
+        .rposition(|window| window == b"/Contents ")
+        .unwrap()
+        + 10;
+    let end = start
+        + input[start..]
+            .iter()
+            .position(|byte| *byte == b' ')
+            .unwrap();
+    let mut broken = input.clone();
+    broken[start..end].fill(b'9');
+
+    let mut buf = Vec::new();
+    let mut file = scannedpdf::PDF::create(
+        std::io::Cursor::new(&mut buf),
+        scannedpdf::PageConfig::new(),
+        5,
+    )
+    .unwrap();
+    file.add_page_from_image(page(), Some("Cover".to_string()), None)
+        .unwrap();
+    assert!(matches!(
+        file.add_pages_from_pdf(std::io::Cursor::new(&broken), None),
+        Err(scannedpdf::Error::InvalidPdf)
+    ));
+    assert_eq!(file.page_count(), 1);
+
+    // So does a stream longer than the file
+    let length = input
+        .windows(8)
+        .position(|window| window == b"/Length ")
+        .unwrap()
+        + 8;
+    let mut broken = input.clone();
+    broken.splice(length..length, b"99999999999".iter().copied());
+    assert!(matches!(
+        file.add_pages_from_pdf(std::io::Cursor::new(&broken), None),
+        Err(scannedpdf::Error::InvalidPdf)
+    ));
+    assert_eq!(file.page_count(), 1);
+
+    // The output is unchanged and still complete
+    file.add_pages_from_pdf(std::io::Cursor::new(&input), None)
+        .unwrap();
+    file.finish().unwrap();
+    let contains = |needle: &[u8]| buf.windows(needle.len()).any(|window| window == needle);
+    assert!(contains(b"/Count 5\n/Kids"));
+    assert!(contains(b"/Count 5\n/First"));
+}
+
+#[test]
+fn split() {
+    std::fs::create_dir_all("./assets/split").unwrap();
+    let page = |shade: u8| {
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(40, 30, image::Rgb([shade; 3])))
+    };
+    let part = |name: &'static str| {
+        move |index: usize| std::fs::File::create(format!("./assets/split/{}{}.pdf", name, index))
+    };
+
+    // Parts could never hold a page
+    for split in [scannedpdf::Split::Pages(0), scannedpdf::Split::Bytes(0)] {
+        let config = scannedpdf::PageConfig::new();
+        assert!(matches!(
+            scannedpdf::SplitPDF::create(part("empty"), config, 5, split),
+            Err(scannedpdf::Error::InvalidSplit)
+        ));
+    }
+
+    let config = scannedpdf::PageConfig::new();
+    let mut file =
+        scannedpdf::SplitPDF::create(part("pages"), config, 5, scannedpdf::Split::Pages(2))
+            .unwrap();
+    for i in 0..5 {
+        let outline = (i == 0).then(|| "Chapter 1".to_string());
+        file.add_page_from_image(page(i * 40), outline, None)
+            .unwrap();
+    }
+    assert!(matches!(
+        file.add_page_from_image(page(0), None, None),
+        Err(scannedpdf::Error::PageOverflow)
+    ));
+    assert_eq!(file.finish().unwrap(), 3);
+
+    for index in 0..3 {
+        let part = std::fs::read(format!("./assets/split/pages{}.pdf", index)).unwrap();
+        let contains = |needle: &[u8]| part.windows(needle.len()).any(|window| window == needle);
+        let pages = if index == 2 { 1 } else { 2 };
+        assert!(contains(format!("/Count {}\n/Kids", pages).as_bytes()));
+        // The chapter continues in every part
+        assert!(contains(b"/Count 1\n/First"));
+    }
+
+    // Noise doesn't compress, about 8 KB per page
+    let noise = |seed: u32| {
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            let n = (x * 7919 + y * 104729 + seed * 15485863).wrapping_mul(2654435761);
+            image::Rgb([(n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8])
+        }))
+    };
+    let limit = 40_000;
+    let config = scannedpdf::PageConfig::new().encoding(scannedpdf::Encoding::Lossless);
+    let mut file =
+        scannedpdf::SplitPDF::create(part("bytes"), config, 12, scannedpdf::Split::Bytes(limit))
+            .unwrap();
+    for i in 0..12 {
+        file.add_page_from_image(noise(i), None, None).unwrap();
+    }
+    let parts = file.finish().unwrap();
+    assert!(parts > 1);
+    for index in 0..parts {
+        let size = std::fs::metadata(format!("./assets/split/bytes{}.pdf", index))
+            .unwrap()
+            .len();
+        assert!(size <= limit, "part {} is {} bytes", index, size);
+    }
+}
+
+#[test]
+fn plain_writer() {
+    let page = || image::DynamicImage::ImageRgb8(image::RgbImage::new(40, 30));
+
+    // `Vec<u8>` is `Write` but not `Seek`, like a pipe
+    let mut plain = Vec::new();
+    let mut file = scannedpdf::PDF::create(&mut plain, scannedpdf::PageConfig::new(), 2).unwrap();
+    file.add_page_from_image(page(), Some("First".to_string()), None)
+        .unwrap();
+    file.add_page_from_image(page(), None, None).unwrap();
+    file.finish().unwrap();
+
+    let mut seekable = Vec::new();
+    let mut file = scannedpdf::PDF::create(
+        std::io::Cursor::new(&mut seekable),
+        scannedpdf::PageConfig::new(),
+        2,
+    )
+    .unwrap();
+    file.add_page_from_image(page(), Some("First".to_string()), None)
+        .unwrap();
+    file.add_page_from_image(page(), None, None).unwrap();
+    file.finish().unwrap();
+
+    assert_eq!(plain, seekable);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_writer() {
+    let page = || image::DynamicImage::ImageRgb8(image::RgbImage::new(40, 30));
+    let images = get_images();
+
+    let mut output = Vec::new();
+    let mut file = scannedpdf::AsyncPDF::create(&mut output, scannedpdf::PageConfig::new(), 2)
+        .await
+        .unwrap();
+    file.add_page_from_image(page(), Some("First".to_string()), None)
+        .await
+        .unwrap();
+    file.add_page_from_path(&images[0], None, None)
+        .await
+        .unwrap();
+    file.finish().await.unwrap();
+
+    // Same bytes as the blocking writer
+    let mut expected = Vec::new();
+    let mut file =
+        scannedpdf::PDF::create(&mut expected, scannedpdf::PageConfig::new(), 2).unwrap();
+    file.add_page_from_image(page(), Some("First".to_string()), None)
+        .unwrap();
+    file.add_page_from_path(&images[0], None, None).unwrap();
+    file.finish().unwrap();
+
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn chunks() {
+    let pages = || {
+        (0..3u8).map(|i| {
+            let image = image::RgbImage::from_pixel(40, 30, image::Rgb([i * 60; 3]));
+            (
+                image::DynamicImage::ImageRgb8(image),
+                Some(format!("Page {}", i + 1)),
+            )
+        })
+    };
+
+    let chunks: Vec<Vec<u8>> = scannedpdf::Chunks::new(pages(), scannedpdf::PageConfig::new(), 3)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    // Header, three pages and the end
+    assert_eq!(chunks.len(), 5);
+
+    let mut expected = Vec::new();
+    let mut file =
+        scannedpdf::PDF::create(&mut expected, scannedpdf::PageConfig::new(), 3).unwrap();
+    for (image, outline) in pages() {
+        file.add_page_from_image(image, outline, None).unwrap();
+    }
+    file.finish().unwrap();
+    assert_eq!(chunks.concat(), expected);
+
+    // Errors end the chunks
+    let config = scannedpdf::PageConfig::new().margin(scannedpdf::Margin::Custom(1000, 1000));
+    let mut chunks = scannedpdf::Chunks::new(pages(), config, 3).unwrap();
+    assert!(chunks.next().unwrap().is_ok());
+    assert!(chunks.next().unwrap().is_err());
+    assert!(chunks.next().is_none());
+
+    // More images than pages
+    let mut chunks = scannedpdf::Chunks::new(pages(), scannedpdf::PageConfig::new(), 2).unwrap();
+    assert_eq!(chunks.by_ref().take(3).filter(Result::is_ok).count(), 3);
+    assert!(matches!(
+        chunks.next(),
+        Some(Err(scannedpdf::Error::PageOverflow))
+    ));
+    assert!(chunks.next().is_none());
+}
+
+#[cfg(feature = "stream")]
+#[tokio::test]
+async fn chunk_stream() {
+    use futures_util::StreamExt;
+
+    let pages = (0..2).map(|_| {
+        (
+            image::DynamicImage::ImageRgb8(image::RgbImage::new(40, 30)),
+            None,
+        )
+    });
+    let chunks: Vec<_> = scannedpdf::Chunks::new(pages, scannedpdf::PageConfig::new(), 2)
+        .unwrap()
+        .into_stream()
+        .collect()
+        .await;
+    assert_eq!(chunks.len(), 4);
+    let pdf = chunks
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap()
+        .concat();
+    assert!(pdf.starts_with(b"%PDF-1.7"));
+    assert!(pdf.ends_with(b"%%EOF\n"));
+}
+
+#[test]
+fn xref_table() {
+    let mut buf = Vec::new();
+    let mut file = scannedpdf::PDF::create(
+        std::io::Cursor::new(&mut buf),
+        scannedpdf::PageConfig::new(),
+        2,
+    )
+    .unwrap();
+    for _ in 0..2 {
+        let image = image::DynamicImage::ImageRgb8(image::RgbImage::new(40, 30));
+        file.add_page_from_image(image, Some("Page".to_string()), None)
+            .unwrap();
+    }
+    file.finish().unwrap();
+
+    let text = String::from_utf8_lossy(&buf);
+    let xref = buf
+        .windows(6)
+        .rposition(|window| window == b"\nxref\n")
+        .unwrap()
+        + 1;
+    let mut lines = buf[xref..].split(|byte| *byte == b'\n').skip(1);
+    let header = std::str::from_utf8(lines.next().unwrap()).unwrap();
+    let size: usize = header.strip_prefix("0 ").unwrap().parse().unwrap();
+    assert!(text.contains(&format!("/Size {}\n", size)));
+
+    // Entries are 20 bytes with their end of line, and every object is listed
+    let entries = &buf[xref + 5 + header.len() + 1..];
+    assert_eq!(&entries[..20], b"0000000000 65535 f \n");
+    for id in 1..size {
+        let entry = &entries[id * 20..id * 20 + 20];
+        assert!(entry.ends_with(b" 00000 n \n"));
+        let offset: usize = std::str::from_utf8(&entry[..10]).unwrap().parse().unwrap();
+        assert!(buf[offset..].starts_with(format!("{} 0 obj\n", id).as_bytes()));
+    }
+    assert!(entries[size * 20..].starts_with(b"trailer\n"));
+}
+
+#[test]
+fn packed_gray_tiff() {
+    std::fs::create_dir_all("./assets/tiff").unwrap();
+    // Uncompressed bilevel and 4-bit pages, black is zero
+    let bilevel: Vec<u8> = (0..8).flat_map(|_| [0x0F, 0xF0]).collect();
+    let levels: Vec<u8> = (0..4)
+        .flat_map(|_| (0..8).map(|i| i * 0x22 + 0x01))
+        .collect();
+    let data = tiff_file(&[(16, 8, 1, 1, 1, bilevel), (16, 4, 4, 1, 1, levels)]);
+    std::fs::write("./assets/tiff/packed.tiff", data).unwrap();
+
+    let config = scannedpdf::PageConfig::new().encoding(scannedpdf::Encoding::Indexed(16));
+    let mut buf = Vec::new();
+    let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 2).unwrap();
+    let pages = file
+        .add_pages_from_path("./assets/tiff/packed.tiff", None, None)
+        .unwrap();
+    assert_eq!(pages.len(), 2);
+    file.finish().unwrap();
+
+    // Samples scaled to 8 bits, in the order they first appear
+    let contains = |needle: &[u8]| buf.windows(needle.len()).any(|window| window == needle);
+    assert!(contains(b"/Indexed /DeviceRGB 1 <000000FFFFFF>"));
+    assert!(contains(b"/Indexed /DeviceRGB 15 <000000111111222222"));
+}
+
+#[test]
+fn tga_file() {
+    // TGA files have no magic bytes, their format comes from the extension
+    std::fs::create_dir_all("./assets/tga").unwrap();
+    let path = "./assets/tga/page.tga";
+    image::RgbImage::from_pixel(40, 30, image::Rgb([200, 100, 50]))
+        .save(path)
+        .unwrap();
+
+    let mut buf = Vec::new();
+    let mut file = scannedpdf::PDF::create(
+        std::io::Cursor::new(&mut buf),
+        scannedpdf::PageConfig::new(),
+        2,
+    )
+    .unwrap();
+    file.add_page_from_path(path, None, None).unwrap();
+    assert_eq!(file.add_pages_from_path(path, None, None).unwrap().len(), 1);
+    file.finish().unwrap();
+
+    let contains = |needle: &[u8]| buf.windows(needle.len()).any(|window| window == needle);
+    assert!(contains(b"/Count 2\n/Kids"));
+}
+
+#[test]
+fn multi_frame_outline() {
+    let _ = std::fs::create_dir("./assets/frames");
+
+    // The first page is blank and skipped
+    let mut tiff = std::fs::File::create("./assets/frames/blank_first.tiff").unwrap();
+    let mut encoder = tiff::encoder::TiffEncoder::new(&mut tiff).unwrap();
+    for shade in [255u8, 0] {
+        encoder
+            .write_image::<tiff::encoder::colortype::Gray8>(100, 100, &vec![shade; 100 * 100])
+            .unwrap();
+    }
+    drop(tiff);
+
+    // A PDF written by this crate
+    let mut file = scannedpdf::create(
+        "./assets/frames/input.pdf",
+        scannedpdf::PageConfig::new(),
+        2,
+    )
+    .unwrap();
+    for _ in 0..2 {
+        let image = image::DynamicImage::ImageRgb8(image::RgbImage::new(40, 30));
+        file.add_page_from_image(image, None, None).unwrap();
+    }
+    file.finish().unwrap();
+
+    let config = scannedpdf::PageConfig::new().blank_page(scannedpdf::BlankPage::Skip(0.01));
+    let mut buf = Vec::new();
+    let mut file = scannedpdf::PDF::create(std::io::Cursor::new(&mut buf), config, 3).unwrap();
+    let pages = file
+        .add_pages_from_path(
+            "./assets/frames/blank_first.tiff",
+            Some("Scan".to_string()),
+            None,
+        )
+        .unwrap();
+    assert!(pages[0].skipped && !pages[1].skipped);
+    let pages = file
+        .add_pages_from_path("./assets/frames/input.pdf", Some("Copy".to_string()), None)
+        .unwrap();
+    assert_eq!(pages.len(), 2);
+    file.finish().unwrap();
+
+    let text = String::from_utf8_lossy(&buf);
+    let kids: Vec<&str> = text
+        .split("/Kids [\n")
+        .nth(1)
+        .unwrap()
+        .split("]")
+        .next()
+        .unwrap()
+        .lines()
+        .collect();
+    assert_eq!(kids.len(), 3);
+    // Both outlines point at the first page written from their file
+    let dests: Vec<&str> = text
+        .split("/Dest [")
+        .skip(1)
+        .map(|dest| dest.split(" /XYZ").next().unwrap())
+        .collect();
+    assert_eq!(dests, [kids[0], kids[1]]);
+}
+
+#[test]
+fn outline_escaping() {
+    let mut buf = Vec::new();
+    let mut file = scannedpdf::PDF::create(
+        std::io::Cursor::new(&mut buf),
+        scannedpdf::PageConfig::new(),
+        2,
+    )
+    .unwrap();
+    let image = || image::DynamicImage::ImageRgb8(image::RgbImage::new(40, 30));
+    file.add_page_from_image(image(), Some("a(b)\\c".to_string()), None)
+        .unwrap();
+    file.finish().unwrap();
+
+    // Parentheses and backslashes are escaped in the UTF-16BE literal string
+    let title = b"/Title (\xFE\xFF\0a\0\\(\0b\0\\)\0\\\\\0c)";
+    let count = |buf: &[u8]| {
+        buf.windows(title.len())
+            .filter(|window| window == title)
+            .count()
+    };
+    assert_eq!(count(&buf), 1);
+
+    // Read back unchanged when appending
+    let mut file = scannedpdf::PDF::append(
+        std::io::Cursor::new(&mut buf),
+        scannedpdf::PageConfig::new(),
+        1,
+    )
+    .unwrap();
+    file.add_page_from_image(image(), None, None).unwrap();
+    file.finish().unwrap();
+    assert_eq!(count(&buf), 2);
+}
+
+/// A PDF with the xref table of older versions of this crate: 19 byte
+/// entries, the last object left out and `/Size` one too low
+fn old_xref_format(pdf: &[u8]) -> Vec<u8> {
+    let xref = pdf
+        .windows(6)
+        .rposition(|window| window == b"\nxref\n")
+        .unwrap()
+        + 1;
+    let header = pdf[xref + 5..].split(|byte| *byte == b'\n').next().unwrap();
+    let size: usize = std::str::from_utf8(&header[2..]).unwrap().parse().unwrap();
+    let entries = &pdf[xref + 5 + header.len() + 1..];
+
+    let mut old = pdf[..xref].to_vec();
+    old.extend(format!("xref\n0 {}\n0000000000 65535 f\n", size - 1).as_bytes());
+    for id in 1..size - 1 {
+        old.extend(&entries[id * 20..id * 20 + 18]);
+        old.push(b'\n');
+    }
+    old.extend(
+        format!(
+            "trailer\n<<\n/Size {}\n/Root 1 0 R\n>>\nstartxref\n{}\n%%EOF\n",
+            size - 1,
+            xref
+        )
+        .as_bytes(),
+    );
+    old
+}
+
+#[test]
+fn append_old_format() {
+    let page = || image::DynamicImage::ImageRgb8(image::RgbImage::new(40, 30));
+
+    for outline in [None, Some("Chapter".to_string())] {
+        let mut buf = Vec::new();
+        let mut file = scannedpdf::PDF::create(
+            std::io::Cursor::new(&mut buf),
+            scannedpdf::PageConfig::new(),
+            2,
+        )
+        .unwrap();
+        file.add_page_from_image(page(), outline.clone(), None)
+            .unwrap();
+        file.add_page_from_image(page(), outline.clone(), None)
+            .unwrap();
+        file.finish().unwrap();
+        let mut buf = old_xref_format(&buf);
+        let original = buf.clone();
+
+        // The highest object number: the last outline, or the contents of
+        // the last page
+        let text = String::from_utf8_lossy(&original);
+        let highest = text
+            .lines()
+            .filter_map(|line| line.strip_suffix(" 0 obj")?.parse::<usize>().ok())
+            .max()
+            .unwrap();
+
+        let mut file = scannedpdf::PDF::append(
+            std::io::Cursor::new(&mut buf),
+            scannedpdf::PageConfig::new(),
+            1,
+        )
+        .unwrap();
+        file.add_page_from_image(page(), Some("New".to_string()), None)
+            .unwrap();
+        file.finish().unwrap();
+
+        // Only the page tree and outlines are redefined
+        let update = String::from_utf8_lossy(&buf[original.len()..]);
+        for id in update
+            .lines()
+            .filter_map(|line| line.strip_suffix(" 0 obj")?.parse::<usize>().ok())
+        {
+            assert!(
+                id == 2 || id == 3 || id > highest,
+                "object {} redefined",
+                id
+            );
+        }
+        assert!(update.contains("/Count 3\n/Kids"));
+        let outlines = if outline.is_some() { 3 } else { 1 };
+        assert!(update.contains(&format!("/Type /Outlines\n/Count {}\n", outlines)));
+        std::fs::create_dir_all("./assets/append").unwrap();
+        std::fs::write(format!("./assets/append/old{}.pdf", outlines), &buf).unwrap();
+    }
+}